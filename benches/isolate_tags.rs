@@ -0,0 +1,58 @@
+//! Tag isolation on a large document with few tags, like a whole script dump, and wrapping
+//! lots of short strings without tags, like UI labels.
+//!
+//! Run with `cargo bench --bench isolate_tags`.
+
+use std::hint::black_box;
+use std::time::Instant;
+
+use hachimi_lib::{wrap_line_count, wrap_text, wrap_text_batch, IsolateTags};
+
+fn bench(name: &str, iterations: u32, mut f: impl FnMut()) {
+    // Warm up the caches before timing
+    f();
+    let start = Instant::now();
+    for _ in 0..iterations {
+        f();
+    }
+    let average = start.elapsed() / iterations;
+    println!("{name:<24} {:>10.3} ms", average.as_secs_f64() * 1000.0);
+}
+
+fn main() {
+    let paragraph =
+        "The quick brown fox jumps over the lazy dog, then it <b>rests</b> for a while. "
+            .repeat(40);
+    let document = format!("{paragraph}\n").repeat(400);
+    println!(
+        "document: {:.1} MB, {} sections",
+        document.len() as f64 / 1_000_000.0,
+        IsolateTags::new(&document).count()
+    );
+
+    bench("isolate_tags", 50, || {
+        black_box(IsolateTags::new(black_box(&document)).count());
+    });
+    bench("wrap_text", 5, || {
+        black_box(wrap_text(black_box(&document), 40, 1.0));
+    });
+    bench("wrap_line_count", 5, || {
+        black_box(wrap_line_count(black_box(&document), 40, 1.0));
+    });
+
+    let labels: Vec<String> = (0..20_000).map(|i| format!("Label {i} value")).collect();
+    bench("wrap_text labels", 20, || {
+        for label in &labels {
+            black_box(wrap_text(black_box(label), 40, 1.0));
+        }
+    });
+    // Includes cloning the labels since the batch takes them by value, like from JS
+    bench("wrap_text_batch labels", 20, || {
+        black_box(wrap_text_batch(black_box(labels.clone()), 40, 1.0));
+    });
+    bench("wrap_line_count labels", 20, || {
+        for label in &labels {
+            black_box(wrap_line_count(black_box(label), 40, 1.0));
+        }
+    });
+}
@@ -0,0 +1,210 @@
+use std::ops::Range;
+
+use crate::{normalize, offset_in, width::SizeStack, LineWrapper, WrapOptions};
+
+/// Remembers how a text was wrapped, so that wrapping it again after an edit only wraps the
+/// paragraphs from the first one that changed, e.g. while the text is being typed in.
+///
+/// The lines are the same as [`crate::wrap_text_with_options`] gives for the new text. A
+/// paragraph (the text up to a `\n`) before the edit is only wrapped again if a tag in it
+/// depends on the text after it, like a `<b>` that's only closed in the edited text. Nothing is
+/// reused when the options change.
+#[derive(Debug, Clone, Default)]
+pub struct WrapCache {
+    /// The normalized text that was wrapped last.
+    text: String,
+    /// Debug output of the options the text was wrapped with, which is cheaper to keep around
+    /// than the options and their borrows. Custom width functions are told apart by address.
+    options: String,
+    paragraphs: Vec<CachedParagraph>,
+    lines: Vec<String>,
+}
+
+/// A paragraph of [`WrapCache::text`] along with the state of the wrapper after it.
+#[derive(Debug, Clone)]
+struct CachedParagraph {
+    /// Byte range of the paragraph in the text, without the `\n`.
+    range: Range<usize>,
+    /// Number of lines up to and including the ones of this paragraph.
+    line_count: usize,
+    /// Byte ranges of the tags that are open after the paragraph.
+    open_tags: Vec<Range<usize>>,
+    sizes: SizeStack,
+    /// Length of each chunk of the paragraph and whether it's a tag, if it has any opening
+    /// brackets. Whether those are tags can depend on the text after the paragraph.
+    chunks: Option<Vec<(usize, bool)>>,
+}
+
+impl WrapCache {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    /// Wraps the string like [`crate::wrap_text_with_options`], reusing the lines of the
+    /// paragraphs at the start that didn't change since the last call.
+    pub fn wrap(&mut self, string: &str, options: &WrapOptions<'_>) -> &[String] {
+        let text = normalize::apply(string, &normalize::replacements(string, options));
+        let options_key = format!("{options:?}");
+        if options_key != self.options {
+            self.clear();
+            self.options = options_key;
+        }
+        let reused = self.reusable_paragraphs(&text, options);
+        self.paragraphs.truncate(reused);
+        self.lines.truncate(
+            self.paragraphs
+                .last()
+                .map_or(0, |paragraph| paragraph.line_count),
+        );
+        self.text.clear();
+        self.text.push_str(&text);
+
+        // Like wrap_lines, blank strings have no lines
+        if text.trim().is_empty() {
+            self.paragraphs.clear();
+            self.lines.clear();
+            return &self.lines;
+        }
+
+        let mut wrapper = LineWrapper::new(options);
+        let mut start = 0;
+        if let Some(last) = self.paragraphs.last() {
+            if last.range.end == text.len() {
+                return &self.lines;
+            }
+            wrapper.sizes = last.sizes.clone();
+            wrapper.open_tags = last
+                .open_tags
+                .iter()
+                .map(|range| &text[range.clone()])
+                .collect();
+            wrapper.taken_lines = last.line_count;
+            start = last.range.end + 1;
+        }
+
+        for line in text[start..].split('\n') {
+            if options
+                .max_lines
+                .is_some_and(|max_lines| wrapper.line_count() >= max_lines)
+            {
+                break;
+            }
+
+            let offset = offset_in(&text, line);
+            let rest = &text[offset + line.len()..];
+            wrapper.wrap_line(line, rest, options);
+            self.lines.extend(
+                wrapper
+                    .take_lines()
+                    .into_iter()
+                    .map(|line| line.into_cow(options).into_owned()),
+            );
+            self.paragraphs.push(CachedParagraph {
+                range: offset..offset + line.len(),
+                line_count: self.lines.len(),
+                open_tags: wrapper
+                    .open_tags
+                    .iter()
+                    .map(|tag| offset_in(&text, tag)..offset_in(&text, tag) + tag.len())
+                    .collect(),
+                sizes: wrapper.sizes.clone(),
+                chunks: chunks(line, rest, options),
+            });
+        }
+        &self.lines
+    }
+
+    /// Forgets the text that was wrapped.
+    pub fn clear(&mut self) {
+        self.text.clear();
+        self.options.clear();
+        self.paragraphs.clear();
+        self.lines.clear();
+    }
+
+    /// Number of paragraphs at the start that are wrapped the same in `text`.
+    fn reusable_paragraphs(&self, text: &str, options: &WrapOptions<'_>) -> usize {
+        let common = self
+            .text
+            .bytes()
+            .zip(text.bytes())
+            .take_while(|(a, b)| a == b)
+            .count();
+        if self.text.len() == text.len() && common == text.len() {
+            return self.paragraphs.len();
+        }
+
+        self.paragraphs
+            .iter()
+            .take_while(|paragraph| {
+                // The paragraph has to be followed by the same `\n`, the last one only stays the
+                // same if nothing changed
+                let end = paragraph.range.end;
+                end < common
+                    && (paragraph.chunks.is_none() || {
+                        let line = &text[paragraph.range.clone()];
+                        chunks(line, &text[end..], options) == paragraph.chunks
+                    })
+            })
+            .count()
+    }
+}
+
+/// The chunks of a paragraph, see [`CachedParagraph::chunks`].
+fn chunks(line: &str, rest: &str, options: &WrapOptions<'_>) -> Option<Vec<(usize, bool)>> {
+    let (open, _) = options.tag_delimiters;
+    line.contains(open).then(|| {
+        options
+            .isolate_tags(line)
+            .with_lookahead(rest)
+            .map(|(chunk, is_tag)| (chunk.len(), is_tag))
+            .collect()
+    })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::wrap_text_with_options;
+
+    #[test]
+    fn wrapping_the_same_text_again_reuses_the_lines() {
+        let mut cache = WrapCache::new();
+        let options = WrapOptions::new(6);
+        let text = "aaa bbb ccc\nddd <b>eee</b>";
+        let lines = cache.wrap(text, &options).to_vec();
+        assert_eq!(lines, wrap_text_with_options(text, &options));
+        let pointer = cache.wrap(text, &options).as_ptr();
+        assert_eq!(cache.wrap(text, &options), lines);
+        assert_eq!(cache.wrap(text, &options).as_ptr(), pointer);
+    }
+
+    #[test]
+    fn changes_wrap_again() {
+        let mut cache = WrapCache::new();
+        let options = WrapOptions::new(8);
+        cache.wrap("aaa bbb ccc\nddd <b>eee", &options);
+        // An edit that closes a tag of an earlier paragraph
+        let text = "aaa bbb ccc\nddd <b>eee\nfff</b>";
+        assert_eq!(
+            cache.wrap(text, &options),
+            wrap_text_with_options(text, &options)
+        );
+        let text = "aaa bbb\nccc";
+        assert_eq!(
+            cache.wrap(text, &options),
+            wrap_text_with_options(text, &options)
+        );
+
+        let options = WrapOptions::new(3);
+        assert_eq!(
+            cache.wrap(text, &options),
+            wrap_text_with_options(text, &options)
+        );
+        let options = options.line_prefix("> ");
+        assert_eq!(
+            cache.wrap(text, &options),
+            wrap_text_with_options(text, &options)
+        );
+    }
+}
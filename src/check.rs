@@ -0,0 +1,272 @@
+use std::fmt;
+
+use crate::{
+    is_void_tag, offset_in, segment::parse_tag, ParsedTags, Segment, WrapOptions, DEFAULT_VOID_TAGS,
+};
+
+/// An unbalanced tag found by [`check_tags`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum TagError<'a> {
+    /// A closing tag without a matching opening tag before it, e.g. a stray `</size>`.
+    UnexpectedClosingTag { offset: usize, name: &'a str },
+    /// An opening tag that is never closed.
+    UnclosedTag { offset: usize, name: &'a str },
+    /// A closing tag that closes its tag while another one opened inside of it is still open,
+    /// e.g. the `</b>` of `<b><i>x</b></i>`. `inner` is the name of the innermost open tag.
+    MisnestedClosingTag {
+        offset: usize,
+        name: &'a str,
+        inner: &'a str,
+    },
+}
+
+impl TagError<'_> {
+    /// Byte offset of the tag in the checked string.
+    pub fn offset(&self) -> usize {
+        match *self {
+            TagError::UnexpectedClosingTag { offset, .. }
+            | TagError::UnclosedTag { offset, .. }
+            | TagError::MisnestedClosingTag { offset, .. } => offset,
+        }
+    }
+
+    pub fn name(&self) -> &str {
+        match *self {
+            TagError::UnexpectedClosingTag { name, .. }
+            | TagError::UnclosedTag { name, .. }
+            | TagError::MisnestedClosingTag { name, .. } => name,
+        }
+    }
+}
+
+impl fmt::Display for TagError<'_> {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            TagError::UnexpectedClosingTag { offset, name } => {
+                write!(f, "unexpected closing tag </{name}> at byte {offset}")
+            }
+            TagError::UnclosedTag { offset, name } => {
+                write!(f, "unclosed tag <{name}> at byte {offset}")
+            }
+            TagError::MisnestedClosingTag {
+                offset,
+                name,
+                inner,
+            } => {
+                write!(
+                    f,
+                    "closing tag </{name}> at byte {offset} is inside of <{inner}>"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for TagError<'_> {}
+
+/// Checks that every tag in the string is balanced and properly nested, returning the first one
+/// that isn't.
+///
+/// Opening tags without a closing tag aren't treated as tags when wrapping, so they are
+/// reported too since they would show up as literal text. Unity applies the ones in
+/// [`crate::DEFAULT_UNCLOSED_TAGS`] like `<size=40>` until the end of the text, but leaving
+/// them open is usually a mistake in a translation, so they're reported as well, see
+/// [`check_tags_with_options`] to allow them. Unclosed tags are only known at the end of the
+/// string, so an unexpected closing tag is reported before any unclosed tags that come after
+/// it.
+pub fn check_tags(s: &str) -> Result<(), TagError<'_>> {
+    check(s, ParsedTags::new(s), DEFAULT_VOID_TAGS, &[], ('<', '>'))
+}
+
+/// Like [`check_tags`], with the tags parsed like the wrapper parses them with `options`. The
+/// tags in [`WrapOptions::unclosed_tags`] can be left open, and the ones in
+/// [`WrapOptions::void_tags`] are never closed.
+pub fn check_tags_with_options<'s>(
+    s: &'s str,
+    options: &WrapOptions<'_>,
+) -> Result<(), TagError<'s>> {
+    check(
+        s,
+        options.parsed_tags(s),
+        options.void_tags,
+        options.unclosed_tags,
+        options.tag_delimiters,
+    )
+}
+
+fn check<'s>(
+    s: &'s str,
+    segments: ParsedTags<'s, '_>,
+    void_tags: &[&str],
+    unclosed_tags: &[&str],
+    (open, close): (char, char),
+) -> Result<(), TagError<'s>> {
+    let mut open_tags: Vec<(usize, &str)> = Vec::new();
+    let mut in_noparse = false;
+    // Tags that look like they were opened, but would be shown as text since they aren't closed
+    let mut unclosed_text: Option<TagError<'_>> = None;
+    for segment in segments {
+        match segment {
+            Segment::Tag {
+                raw,
+                name,
+                closing,
+                self_closing,
+            } => {
+                if in_noparse && !(closing && name.eq_ignore_ascii_case("noparse")) {
+                    continue;
+                }
+                let offset = offset_in(s, raw);
+                if closing {
+                    let Some(i) = open_tags
+                        .iter()
+                        .rposition(|(_, open)| open.eq_ignore_ascii_case(name))
+                    else {
+                        let error = TagError::UnexpectedClosingTag { offset, name };
+                        return Err(unclosed_text.unwrap_or(error));
+                    };
+                    // Tags that can be left open inside of it just stay open
+                    if let Some(&(_, inner)) = open_tags[i + 1..]
+                        .iter()
+                        .rev()
+                        .find(|(_, inner)| !is_void_tag(unclosed_tags, inner))
+                    {
+                        let error = TagError::MisnestedClosingTag {
+                            offset,
+                            name,
+                            inner,
+                        };
+                        return Err(unclosed_text.unwrap_or(error));
+                    }
+                    open_tags.remove(i);
+                    in_noparse = false;
+                } else if !self_closing && !is_void_tag(void_tags, name) {
+                    open_tags.push((offset, name));
+                    in_noparse = name.eq_ignore_ascii_case("noparse");
+                }
+            }
+            Segment::Text(text)
+                if !in_noparse
+                    && text.starts_with(open)
+                    && text[1..].starts_with(char::is_alphabetic) =>
+            {
+                // A tag is only isolated if it's closed later on, so this might be an unclosed one
+                let Some(end) = text.find(close) else {
+                    continue;
+                };
+                if let Segment::Tag {
+                    name,
+                    closing: false,
+                    ..
+                } = parse_tag(&text[..end + 1])
+                {
+                    if unclosed_text.is_none()
+                        && !name.is_empty()
+                        && name.bytes().all(|b| b.is_ascii_alphabetic() || b == b'-')
+                        && !is_void_tag(void_tags, name)
+                    {
+                        let offset = offset_in(s, text);
+                        unclosed_text = Some(TagError::UnclosedTag { offset, name });
+                    }
+                }
+            }
+            Segment::Text(_) => {}
+        }
+    }
+
+    // Unity applies these until the end of the text, so they can be allowed to stay open
+    let unclosed_tag = open_tags
+        .iter()
+        .find(|(_, name)| !is_void_tag(unclosed_tags, name))
+        .map(|&(offset, name)| TagError::UnclosedTag { offset, name });
+    match (unclosed_text, unclosed_tag) {
+        (Some(a), Some(b)) => Err(if a.offset() < b.offset() { a } else { b }),
+        (a, b) => a.or(b).map_or(Ok(()), Err),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn nested_tags_with_the_same_name() {
+        assert_eq!(check_tags("<size=1><size=2>x</size></size>"), Ok(()));
+        assert_eq!(
+            check_tags("<color=red><color=blue>x</color></color>"),
+            Ok(())
+        );
+        assert_eq!(
+            check_tags("<size=1><size=2>x</size></size></size>"),
+            Err(TagError::UnexpectedClosingTag {
+                offset: 31,
+                name: "size"
+            })
+        );
+        assert_eq!(
+            check_tags("<b><b>x</b>"),
+            Err(TagError::UnclosedTag {
+                offset: 0,
+                name: "b"
+            })
+        );
+    }
+
+    #[test]
+    fn stray_closing_tag() {
+        assert_eq!(
+            check_tags("some </size>text"),
+            Err(TagError::UnexpectedClosingTag {
+                offset: 5,
+                name: "size"
+            })
+        );
+    }
+
+    #[test]
+    fn unclosed_tags() {
+        assert_eq!(
+            check_tags("x <size=16>y"),
+            Err(TagError::UnclosedTag {
+                offset: 2,
+                name: "size"
+            })
+        );
+        // Unless the tag is allowed to stay open
+        let options = WrapOptions::new(0);
+        assert_eq!(check_tags_with_options("x <size=16>y", &options), Ok(()));
+        assert_eq!(
+            check_tags_with_options("x <b>y", &options),
+            Err(TagError::UnclosedTag {
+                offset: 2,
+                name: "b"
+            })
+        );
+        let options = options.unclosed_tags(&["b"]);
+        assert_eq!(check_tags_with_options("x <b>y", &options), Ok(()));
+    }
+
+    #[test]
+    fn misnested_tags() {
+        assert_eq!(
+            check_tags("<b><i>x</b></i>"),
+            Err(TagError::MisnestedClosingTag {
+                offset: 7,
+                name: "b",
+                inner: "i"
+            })
+        );
+        assert_eq!(
+            check_tags("<b><color=red>x</b>"),
+            Err(TagError::MisnestedClosingTag {
+                offset: 15,
+                name: "b",
+                inner: "color"
+            })
+        );
+        assert_eq!(
+            check_tags_with_options("<b><color=red>x</b>", &WrapOptions::new(0)),
+            Ok(())
+        );
+    }
+}
@@ -0,0 +1,25 @@
+use std::fmt;
+
+/// Why [`crate::try_wrap_text`] couldn't wrap the text.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum WrapError {
+    /// The optimal fit algorithm overflowed while computing the cost of the lines, which
+    /// happens with absurdly large line widths or penalties.
+    Overflow,
+    /// An option has a value that can't be wrapped to, e.g. a NaN multiplier.
+    InvalidOptions(&'static str),
+    /// A word is wider than the line with [`crate::Overflow::Error`].
+    WordTooWide,
+}
+
+impl fmt::Display for WrapError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            WrapError::Overflow => write!(f, "the optimal fit algorithm overflowed"),
+            WrapError::InvalidOptions(reason) => write!(f, "invalid wrap options: {reason}"),
+            WrapError::WordTooWide => write!(f, "a word is wider than the line"),
+        }
+    }
+}
+
+impl std::error::Error for WrapError {}
@@ -0,0 +1,143 @@
+use textwrap::core::Fragment;
+
+use crate::{is_closing_tag, word::Word, WrapOptions};
+
+/// The characters that lines can't start or end with, see [`crate::WrapOptions::kinsoku`].
+#[derive(Debug, Clone, Copy)]
+pub struct KinsokuChars<'a> {
+    /// Characters that can't start a line, like closing brackets, punctuation and small kana.
+    pub no_start: &'a str,
+    /// Characters that can't end a line, i.e. opening brackets.
+    pub no_end: &'a str,
+}
+
+impl Default for KinsokuChars<'_> {
+    fn default() -> Self {
+        Self {
+            no_start: "、。，．・：；？！゛゜ヽヾゝゞ々〻ー‐゠–〜～）］｝〕〉》」』】〙〗〟’”｠»\
+                       ぁぃぅぇぉっゃゅょゎゕゖァィゥェォッャュョヮヵヶㇰㇱㇲㇳㇴㇵㇶㇷㇸㇹㇺㇻㇼㇽㇾㇿ\
+                       …‥,.:;?!)]}",
+            no_end: "（［｛〔〈《「『【〘〖〝‘“｟«([{",
+        }
+    }
+}
+
+impl KinsokuChars<'_> {
+    /// Whether `s` starts with a character that can't start a line.
+    pub(crate) fn is_no_start(&self, s: &str) -> bool {
+        s.chars().next().is_some_and(|c| self.no_start.contains(c))
+    }
+
+    /// Whether `s` ends with a character that can't end a line.
+    pub(crate) fn is_no_end(&self, s: &str) -> bool {
+        s.chars().last().is_some_and(|c| self.no_end.contains(c))
+    }
+}
+
+/// Moves the line breaks so that no line starts or ends with a forbidden character. The
+/// character at the start of a line is pulled onto the previous line, even if it doesn't
+/// quite fit, and the word at the end of a line is pushed onto the next one.
+pub(crate) fn apply<'a, 'b>(
+    words: &'b [Word<'a>],
+    lines: Vec<&'b [Word<'a>]>,
+    options: &WrapOptions<'_>,
+) -> Vec<&'b [Word<'a>]> {
+    if !options.kinsoku || lines.len() < 2 {
+        return lines;
+    }
+
+    let chars = &options.kinsoku_chars;
+    let is_visible = |word: &Word<'_>| !(word.is_tag && word.width() == 0.0);
+    let mut starts: Vec<usize> = lines
+        .iter()
+        .scan(0, |start, line| {
+            let line_start = *start;
+            *start += line.len();
+            Some(line_start)
+        })
+        .collect();
+    starts.push(words.len());
+
+    for i in 1..starts.len() - 1 {
+        let end = starts[i + 1];
+        // Pull the forbidden words at the start of the line onto the previous one, along with
+        // the tags in front of them and the closing tags right after them
+        while let Some(first) = (starts[i]..end).find(|&j| is_visible(&words[j])) {
+            if !chars.is_no_start(words[first].word) {
+                break;
+            }
+            let mut new_start = first + 1;
+            while new_start < end && is_closing_tag(&words[new_start]) {
+                new_start += 1;
+            }
+            starts[i] = new_start;
+        }
+
+        // Push a forbidden word at the end of the previous line onto this one, as long as
+        // something is left on the previous line
+        let previous = starts[i - 1]..starts[i];
+        let mut visible = previous.clone().rev().filter(|&j| is_visible(&words[j]));
+        if let (Some(last), Some(_)) = (visible.next(), visible.next()) {
+            if chars.is_no_end(words[last].word) {
+                let mut new_start = last;
+                while new_start > previous.start
+                    && words[new_start - 1].is_tag
+                    && !is_closing_tag(&words[new_start - 1])
+                {
+                    new_start -= 1;
+                }
+                starts[i] = new_start;
+            }
+        }
+    }
+
+    // Lines that were pulled up entirely are gone
+    starts.dedup();
+    starts
+        .windows(2)
+        .map(|pair| &words[pair[0]..pair[1]])
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{wrap_text_with_options, LineBreakKind, WrapOptions};
+
+    #[test]
+    fn opening_brackets_move_to_the_next_line() {
+        let text = "これは「テスト」です。";
+        let options = |line_break| WrapOptions::new(4).kinsoku(true).line_break(line_break);
+        assert_eq!(
+            wrap_text_with_options(text, &options(LineBreakKind::Uax14)),
+            vec!["こ", "れは", "「テ", "ス", "ト」", "で", "す。"]
+        );
+        assert_eq!(
+            wrap_text_with_options(text, &options(LineBreakKind::Words)),
+            vec!["これ", "は", "「テ", "スト」", "です。"]
+        );
+        assert_eq!(
+            wrap_text_with_options("ab ( cd", &WrapOptions::new(4).kinsoku(true)),
+            vec!["ab", "( cd"]
+        );
+    }
+
+    #[test]
+    fn lines_dont_start_or_end_with_forbidden_chars() {
+        let text = "ああ。いい、うう）ええ」おお「かか（きき";
+        for line_break in [LineBreakKind::Uax14, LineBreakKind::Words] {
+            for width in 3..16 {
+                let options = WrapOptions::new(width).kinsoku(true).line_break(line_break);
+                for line in wrap_text_with_options(text, &options) {
+                    assert!(
+                        !line.starts_with(['。', '、', '）', '」']),
+                        "{line:?} at {width} with {line_break:?}"
+                    );
+                    assert!(
+                        !line.ends_with(['「', '（']),
+                        "{line:?} at {width} with {line_break:?}"
+                    );
+                }
+            }
+        }
+    }
+}
@@ -1,16 +1,113 @@
-use std::borrow::Cow;
+use std::{borrow::Cow, convert::Infallible};
 
-use textwrap::{core::Word, wrap_algorithms, WordSeparator::UnicodeBreakProperties};
+use textwrap::{word_splitters::WordSplitter, wrap_algorithms, WordSeparator};
+use unicode_segmentation::UnicodeSegmentation;
+#[cfg(feature = "wasm")]
 use wasm_bindgen::prelude::*;
 
-pub struct IsolateTags<'a> {
+mod cache;
+mod check;
+mod error;
+mod kinsoku;
+mod normalize;
+mod optimal_fit;
+mod pages;
+mod rewrap;
+mod rtl;
+mod runs;
+mod segment;
+mod width;
+mod word;
+
+pub use cache::WrapCache;
+pub use check::{check_tags, check_tags_with_options, TagError};
+pub use error::WrapError;
+#[cfg(feature = "hyphenation")]
+pub use hyphenation::Language;
+pub use kinsoku::KinsokuChars;
+pub use normalize::StripChars;
+pub use pages::{wrap_text_pages, wrap_text_pages_with_options};
+pub use rewrap::{rewrap, rewrap_with_options};
+pub use runs::{wrap_text_runs, wrap_text_runs_with_options, Run, StyledLine};
+pub use segment::{ParsedTags, Segment};
+pub use textwrap::core::Fragment;
+pub use textwrap::wrap_algorithms::Penalties;
+use width::{Case, Length, SizeStack};
+pub use width::{CharWidth, FontMetrics, TagWidth, TagWidthFn};
+pub use word::Word;
+
+/// Splits a string into tags and the text between them, yielding each chunk along with
+/// whether it's a tag.
+///
+/// The chunks are slices of the string in order, so joining all of them gives back the exact
+/// input. Breaking whitespace right after a tag belongs to the tag chunk by default, e.g.
+/// `a</b> b` is split into `a`, `</b> ` and `b`, see [`IsolateTags::tag_whitespace`]. Tags
+/// without a closing tag are text, see [`IsolateTags::void_tags`] and
+/// [`IsolateTags::unclosed_tags`].
+///
+/// A tag chunk always ends with the closing bracket of the tag (and the whitespace after it).
+/// An opening bracket that isn't followed by one before the end of the string is text, so
+/// input that was cut off in the middle of a tag like `hello <colo` never yields a tag, not
+/// even for the tags that don't need a closing tag.
+pub struct IsolateTags<'a, 't> {
     s: &'a str,
     bytes: std::str::Bytes<'a>,
     i: usize,
+    /// End of the chunks that haven't been yielded from the back yet.
+    end: usize,
+    /// Start of the text of the `<noparse>` that was closed by the last chunk yielded from the
+    /// back, that text is the next chunk.
+    noparse_start: Option<usize>,
     current_byte: Option<u8>,
+    /// Whether the last tag was `<noparse>`, i.e. the following text until `</noparse>` has to
+    /// be yielded verbatim.
+    in_noparse: bool,
+    /// Allow spaces before the tag name, e.g. `< b >` or `</ b>`.
+    lenient: bool,
+    /// Text after `s` that is also searched for closing tags, so that a tag can be closed after
+    /// the end of the paragraph it's in.
+    lookahead: &'a str,
+    /// The ascii characters that start and end a tag, `<` and `>` by default.
+    open: u8,
+    close: u8,
+    /// Names of the tags that never have a closing tag.
+    void_tags: &'t [&'t str],
+    /// Names of the tags that are still tags without a closing tag, applying until the end.
+    unclosed_tags: &'t [&'t str],
+    /// Where the next closing tag of each name is, so that text full of unclosed tags isn't
+    /// searched again for every one of them.
+    closing_tags: Vec<ClosingTag>,
+    tag_whitespace: TagWhitespace,
+}
+
+/// Which chunk of [`IsolateTags`] the breaking whitespace right after a tag goes to.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum TagWhitespace {
+    /// The end of the tag chunk, e.g. `</b> ` and `b`. The whitespace is a break opportunity
+    /// after the tag, which is how the wrapper sees it too.
+    #[default]
+    WithTag,
+    /// The start of the following text chunk, e.g. `</b>` and ` b`, or a chunk of its own if
+    /// another tag comes right after it. Tags then never contain anything but the tag itself.
+    WithText,
 }
 
-impl<'a> IsolateTags<'a> {
+/// The end, name and closing-ness of a tag parsed by [`IsolateTags::tag_at`], or where
+/// parsing it failed.
+type TagAt<'a> = Result<(usize, &'a str, bool), usize>;
+
+/// The next closing tag for a name, see [`IsolateTags::has_closing_tag`].
+struct ClosingTag {
+    /// Byte range of the name in the string, so that it can be kept for the same string.
+    name: std::ops::Range<usize>,
+    /// Offset of the next closing tag in the string, at or after where it was searched from.
+    next: Option<usize>,
+    /// Whether there's one in the lookahead, only searched for once there's none in the string.
+    in_lookahead: Option<bool>,
+}
+
+impl<'a, 't> IsolateTags<'a, 't> {
     pub fn new(s: &'a str) -> Self {
         let mut bytes = s.bytes();
         Self {
@@ -18,53 +115,163 @@ impl<'a> IsolateTags<'a> {
             s,
             bytes,
             i: 0,
+            end: s.len(),
+            noparse_start: None,
+            in_noparse: false,
+            lenient: false,
+            lookahead: "",
+            open: b'<',
+            close: b'>',
+            void_tags: DEFAULT_VOID_TAGS,
+            unclosed_tags: DEFAULT_UNCLOSED_TAGS,
+            closing_tags: Vec::new(),
+            tag_whitespace: TagWhitespace::default(),
+        }
+    }
+
+    /// Like [`IsolateTags::new`], but with tags delimited by `open` and `close` instead of `<`
+    /// and `>`, e.g. `[b]bold[/b]`. Closing tags still start with a `/`.
+    ///
+    /// # Panics
+    ///
+    /// If either of the delimiters isn't an ascii character.
+    pub fn with_delimiters(s: &'a str, open: char, close: char) -> Self {
+        assert!(
+            open.is_ascii() && close.is_ascii(),
+            "tag delimiters must be ascii"
+        );
+        Self {
+            open: open as u8,
+            close: close as u8,
+            ..Self::new(s)
         }
     }
+
+    pub(crate) fn with_lookahead(mut self, lookahead: &'a str) -> Self {
+        self.lookahead = lookahead;
+        self
+    }
+
+    /// Names of the tags that are complete on their own, without a closing tag, instead of
+    /// [`DEFAULT_VOID_TAGS`]. Other tags are only treated as tags if they're closed later on.
+    pub fn void_tags(mut self, void_tags: &'t [&'t str]) -> Self {
+        self.void_tags = void_tags;
+        self
+    }
+
+    /// Names of the tags that don't need a closing tag, instead of [`DEFAULT_UNCLOSED_TAGS`].
+    /// Unity applies an unclosed tag like `<size=40>` until the end of the text, so these are
+    /// treated as tags whether or not they're closed, unlike [`Self::void_tags`] they still
+    /// have to be closed to end before that.
+    pub fn unclosed_tags(mut self, unclosed_tags: &'t [&'t str]) -> Self {
+        self.unclosed_tags = unclosed_tags;
+        self
+    }
+
+    /// Allow spaces after `<` and `</` before the tag name, like Unity does in some cases.
+    /// Machine-generated text sometimes contains these, strict parsing treats them as text.
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.lenient = lenient;
+        self
+    }
+
+    /// Which chunk the whitespace after a tag belongs to, the tag chunk by default.
+    pub fn tag_whitespace(mut self, tag_whitespace: TagWhitespace) -> Self {
+        self.tag_whitespace = tag_whitespace;
+        self
+    }
 }
 
-impl<'a> Iterator for IsolateTags<'a> {
+impl<'a> Iterator for IsolateTags<'a, '_> {
     type Item = (&'a str, bool);
 
     fn next(&mut self) -> Option<Self::Item> {
         self.current_byte?;
 
         let start = self.i;
-        let mut tag_start = 0;
+        if std::mem::take(&mut self.in_noparse) {
+            // The closing tag must exist since it was checked when the tag was parsed
+            let len = find_closing_tag(&self.s[start..self.end], "noparse", self.syntax())
+                .unwrap_or(self.end - start);
+            if len > 0 {
+                self.i += len;
+                self.bytes = self.s[self.i..self.end].bytes();
+                self.current_byte = self.bytes.next();
+                return Some((&self.s[start..self.i], false));
+            }
+        }
+
+        // Start of the tag name, after the '<' or '</' and any spaces that are allowed
+        let mut name_start = 0;
         let mut in_tag = false;
         let mut in_closing_tag = false;
         let mut expecting_tag_name = false;
+        let mut is_noparse = false;
+        // The quote character of the attribute value we're in, if any
+        let mut quote = None;
 
         while let Some(c) = self.current_byte {
-            if in_tag {
+            if let Some(q) = quote {
+                // Anything goes inside quotes, including the closing bracket
+                if c == q {
+                    quote = None;
+                }
+            } else if in_tag {
                 match c {
-                    b'>' | b'=' | b' ' => 'tag_name_end: {
+                    b' ' if self.lenient && expecting_tag_name && self.i == name_start => {
+                        name_start += 1;
+                    }
+                    c if c == self.close || c == b'=' || c == b' ' => 'tag_name_end: {
                         if expecting_tag_name {
-                            if !in_closing_tag {
+                            let tag_name = &self.s[name_start..self.i];
+                            if tag_name.is_empty() {
+                                // `<>` and `< =x>` aren't tags
+                                in_tag = false;
+                                break 'tag_name_end;
+                            }
+                            if !in_closing_tag && !is_void_tag(self.void_tags, tag_name) {
                                 // Check for a matching closing tag after
-                                let tag_name = &self.s[tag_start + 1..self.i];
-                                let mut closing_tag = String::with_capacity(3 + tag_name.len());
-                                closing_tag += "</";
-                                closing_tag += tag_name;
-                                closing_tag += ">";
-                                if !self.s[self.i..].contains(&closing_tag) {
+                                let syntax = self.syntax();
+                                let mut depth = 0;
+                                if is_void_tag(self.unclosed_tags, tag_name) {
+                                    // Applies until the end if it isn't closed
+                                } else if !self.has_closing_tag(name_start..self.i)
+                                    || find_matching_closing_tag(
+                                        &self.s[self.i..],
+                                        tag_name,
+                                        syntax,
+                                        &mut depth,
+                                    )
+                                    .or_else(|| {
+                                        find_matching_closing_tag(
+                                            self.lookahead,
+                                            tag_name,
+                                            syntax,
+                                            &mut depth,
+                                        )
+                                    })
+                                    .is_none()
+                                {
                                     in_tag = false;
                                     break 'tag_name_end;
                                 }
+                                is_noparse = tag_name.eq_ignore_ascii_case("noparse");
                             }
                             expecting_tag_name = false;
                         }
 
-                        if c == b'>' {
+                        if c == self.close {
+                            // Skip the whitespace after the tag, this has to be done on chars
+                            // since it can contain multi-byte whitespace like U+3000
+                            let rest = &self.s[self.i + 1..self.end];
                             self.i += 1;
-                            self.current_byte = self.bytes.next();
-                            while let Some(ws) = self.current_byte {
-                                if char::from(ws).is_whitespace() {
-                                    self.i += 1;
-                                    self.current_byte = self.bytes.next();
-                                    continue;
-                                }
-                                break;
+                            if self.tag_whitespace == TagWhitespace::WithTag {
+                                self.i += rest.len()
+                                    - rest.trim_start_matches(is_breaking_whitespace).len();
                             }
+                            self.bytes = self.s[self.i..self.end].bytes();
+                            self.current_byte = self.bytes.next();
+                            self.in_noparse = is_noparse;
                             return Some((&self.s[start..self.i], true));
                         } else if in_closing_tag {
                             // Invalid character
@@ -72,26 +279,51 @@ impl<'a> Iterator for IsolateTags<'a> {
                         }
                     }
                     b'/' => {
-                        if self.i == tag_start + 1 {
+                        if self.i == name_start && !in_closing_tag {
                             in_closing_tag = true;
+                            name_start += 1;
                         } else if expecting_tag_name {
-                            in_tag = false;
+                            // Only void tags can be self-closing (e.g. <br/>)
+                            if is_void_tag(self.void_tags, &self.s[name_start..self.i]) {
+                                expecting_tag_name = false;
+                            } else {
+                                in_tag = false;
+                            }
                         }
                     }
+                    b'"' | b'\''
+                        if !in_closing_tag
+                            && self.s.as_bytes()[self.i - 1] == b'='
+                            && self.s.as_bytes()[self.i + 1..].contains(&c) =>
+                    {
+                        // Only a quote at the start of a value that is closed later starts a
+                        // quoted value, so apostrophes in unquoted values are left alone
+                        quote = Some(c);
+                    }
                     _ => {
-                        if expecting_tag_name && !char::from(c).is_ascii_alphabetic() {
+                        // Names like `margin-left` can have hyphens, but not at the start
+                        let is_name_char =
+                            c.is_ascii_alphabetic() || (c == b'-' && self.i > name_start);
+                        if expecting_tag_name && !is_name_char {
                             in_tag = false;
                         }
                     }
                 }
-            } else if c == b'<' {
+            } else if c == self.open {
                 if start == self.i {
                     in_tag = true;
                     expecting_tag_name = true;
-                    tag_start = self.i;
+                    name_start = self.i + 1;
                 } else {
                     break;
                 }
+            } else {
+                // Outside of tags only the next opening bracket matters, so skip right to it
+                let rest = &self.s.as_bytes()[self.i..self.end];
+                self.i += memchr::memchr(self.open, rest).unwrap_or(rest.len());
+                self.bytes = self.s[self.i..self.end].bytes();
+                self.current_byte = self.bytes.next();
+                continue;
             }
 
             self.i += 1;
@@ -102,16 +334,446 @@ impl<'a> Iterator for IsolateTags<'a> {
     }
 }
 
-#[wasm_bindgen]
+/// Yields the same chunks as iterating from the front, in reverse, so that e.g. the last tag
+/// before a position can be found without scanning everything before it. The two ends can be
+/// mixed, they meet in the middle.
+///
+/// Whether an opening tag is a tag still depends on the text after it, so each one is checked
+/// against the rest of the string like from the front. Where a chunk starts is worked out from
+/// the brackets before it instead, which can come out differently from the front when a quoted
+/// attribute value contains whole other tags, like `<link="<b>x</b>">`.
+impl DoubleEndedIterator for IsolateTags<'_, '_> {
+    fn next_back(&mut self) -> Option<Self::Item> {
+        let end = self.end;
+        if end <= self.i {
+            return None;
+        }
+
+        if let Some(start) = self.noparse_start.take() {
+            let start = start.max(self.i);
+            if start < end {
+                self.set_end(start);
+                return Some((&self.s[start..end], false));
+            }
+        }
+
+        let Some(last) =
+            memchr::memrchr(self.open, &self.s.as_bytes()[self.i..end]).map(|j| self.i + j)
+        else {
+            let start = self.i;
+            self.set_end(start);
+            return Some((&self.s[start..end], false));
+        };
+
+        // The end of a noparse is a tag whatever comes before it, since that's all text
+        let result = self.tag_at(last);
+        let noparse_start = match result {
+            Ok((_, name, true)) if name.eq_ignore_ascii_case("noparse") => {
+                self.noparse_text_start(last)
+            }
+            _ => None,
+        };
+        let (start, result) = match noparse_start {
+            Some(_) => (last, result),
+            None => self.chunk_start(self.i, last, end),
+        };
+        let tag = result.ok().filter(|&(tag_end, ..)| tag_end <= end);
+
+        let Some((tag_end, _, closing)) = tag else {
+            self.set_end(start);
+            return Some((&self.s[start..end], false));
+        };
+        let chunk_end = self.tag_chunk_end(tag_end, end);
+        if chunk_end < end {
+            self.set_end(chunk_end);
+            return Some((&self.s[chunk_end..end], false));
+        }
+
+        if closing {
+            self.noparse_start = noparse_start;
+        }
+        self.set_end(start);
+        Some((&self.s[start..end], true))
+    }
+}
+
+/// Like [`str::find`], but ignoring ascii case (Unity treats `<B>` and `<b>` as the same tag).
+fn find_ignore_ascii_case(haystack: &str, needle: &str) -> Option<usize> {
+    haystack
+        .as_bytes()
+        .windows(needle.len())
+        .position(|window| window.eq_ignore_ascii_case(needle.as_bytes()))
+}
+
+/// How tags are written, see [`IsolateTags::with_delimiters`] and [`IsolateTags::lenient`].
+#[derive(Clone, Copy)]
+struct TagSyntax {
+    open: char,
+    close: char,
+    lenient: bool,
+}
+
+impl<'a, 't> IsolateTags<'a, 't> {
+    fn syntax(&self) -> TagSyntax {
+        TagSyntax {
+            open: char::from(self.open),
+            close: char::from(self.close),
+            lenient: self.lenient,
+        }
+    }
+
+    /// Continues yielding chunks from the front at `start`, which has to be the start of a chunk.
+    fn set_start(&mut self, start: usize) {
+        self.i = start;
+        self.bytes = self.s[start..self.end].bytes();
+        self.current_byte = self.bytes.next();
+    }
+
+    /// Stops yielding chunks from the front at `end`, after it was yielded from the back.
+    fn set_end(&mut self, end: usize) {
+        self.end = end;
+        self.bytes = self.s[self.i..end].bytes();
+        self.current_byte = self.bytes.next();
+    }
+
+    /// Start of the chunk that `last`, the last opening bracket before `end`, is in, along with
+    /// the tag at that start. Brackets after `from` can be part of an earlier tag like
+    /// `<link="a<b">`, or be swallowed by a broken one like the second bracket of `<<b>`, which
+    /// is text up to the bracket after it. So this goes back to a bracket that nothing reaches,
+    /// as far as an unquoted closing bracket, and parses forward from there.
+    fn chunk_start(&self, from: usize, last: usize, end: usize) -> (usize, TagAt<'a>) {
+        let reaches = |result: &TagAt<'_>, j: usize| match *result {
+            Ok((tag_end, ..)) => tag_end > j,
+            Err(failed_at) => failed_at >= j,
+        };
+        let mut start = last;
+        let mut result = self.tag_at(last);
+        let mut before = last;
+        while let Some(prev) =
+            memchr::memrchr(self.open, &self.s.as_bytes()[from..before]).map(|j| from + j)
+        {
+            let prev_result = self.tag_at(prev);
+            if reaches(&prev_result, start) {
+                start = prev;
+                result = prev_result;
+            } else {
+                // Tags only go past a closing bracket if it's quoted
+                let between = &self.s.as_bytes()[prev..before];
+                if between.contains(&self.close)
+                    && !between.iter().any(|&c| c == b'"' || c == b'\'')
+                {
+                    break;
+                }
+            }
+            before = prev;
+        }
+        loop {
+            let stop = match result {
+                Ok((tag_end, ..)) => tag_end,
+                Err(failed_at) => failed_at + 1,
+            };
+            let Some(next) = self.s.as_bytes()[stop.min(end)..end]
+                .iter()
+                .position(|&c| c == self.open)
+            else {
+                break;
+            };
+            start = stop + next;
+            result = self.tag_at(start);
+        }
+        (start, result)
+    }
+
+    /// Parses the tag at the opening bracket at `start` the same way as [`Iterator::next`],
+    /// returning the end of it along with its name and whether it's a closing tag. A broken tag
+    /// returns where it failed, the text goes on from the byte after that.
+    fn tag_at(&self, start: usize) -> TagAt<'a> {
+        let bytes = self.s.as_bytes();
+        let mut name_start = start + 1;
+        let mut name = None;
+        let mut closing = false;
+        let mut quote = None;
+        for (i, &c) in bytes.iter().enumerate().skip(start + 1) {
+            if let Some(q) = quote {
+                if c == q {
+                    quote = None;
+                }
+                continue;
+            }
+            match c {
+                b' ' if self.lenient && name.is_none() && i == name_start => name_start += 1,
+                c if c == self.close || c == b'=' || c == b' ' => {
+                    if name.is_none() {
+                        let tag_name = &self.s[name_start..i];
+                        if tag_name.is_empty() || !(closing || self.is_closed(tag_name, i)) {
+                            return Err(i);
+                        }
+                        name = Some(tag_name);
+                    }
+                    match name {
+                        Some(name) if c == self.close => return Ok((i + 1, name, closing)),
+                        _ if closing => return Err(i),
+                        _ => {}
+                    }
+                }
+                b'/' => {
+                    if i == name_start && !closing {
+                        closing = true;
+                        name_start += 1;
+                    } else if name.is_none() {
+                        let tag_name = &self.s[name_start..i];
+                        if !is_void_tag(self.void_tags, tag_name) {
+                            return Err(i);
+                        }
+                        name = Some(tag_name);
+                    }
+                }
+                b'"' | b'\'' if !closing && bytes[i - 1] == b'=' && bytes[i + 1..].contains(&c) => {
+                    quote = Some(c);
+                }
+                _ => {
+                    let is_name_char = c.is_ascii_alphabetic() || (c == b'-' && i > name_start);
+                    if name.is_none() && !is_name_char {
+                        return Err(i);
+                    }
+                }
+            }
+        }
+        Err(bytes.len())
+    }
+
+    /// Whether the opening tag `name` that ends at `i` is a tag, i.e. it's closed later on or
+    /// doesn't need to be.
+    fn is_closed(&self, name: &str, i: usize) -> bool {
+        if is_void_tag(self.void_tags, name) || is_void_tag(self.unclosed_tags, name) {
+            return true;
+        }
+        let syntax = self.syntax();
+        let mut depth = 0;
+        find_matching_closing_tag(&self.s[i..], name, syntax, &mut depth)
+            .or_else(|| find_matching_closing_tag(self.lookahead, name, syntax, &mut depth))
+            .is_some()
+    }
+
+    /// End of the chunk of a tag that ends at `tag_end`, see [`IsolateTags::tag_whitespace`].
+    fn tag_chunk_end(&self, tag_end: usize, end: usize) -> usize {
+        match self.tag_whitespace {
+            TagWhitespace::WithTag => {
+                let rest = &self.s[tag_end..end];
+                end - rest.trim_start_matches(is_breaking_whitespace).len()
+            }
+            TagWhitespace::WithText => tag_end,
+        }
+    }
+
+    /// Start of the text inside of the `<noparse>` that is closed by the `</noparse>` at
+    /// `closing`, if it closes one.
+    fn noparse_text_start(&self, closing: usize) -> Option<usize> {
+        // The noparse is opened by the first `<noparse>` after the previous `</noparse>`
+        let syntax = self.syntax();
+        let mut from = 0;
+        while let Some(j) = find_closing_tag(&self.s[from..closing], "noparse", syntax) {
+            from += j + 1;
+        }
+
+        let bytes = &self.s.as_bytes()[..closing];
+        for j in memchr::memchr_iter(self.open, &bytes[from..]).map(|j| from + j) {
+            let rest = self.s[j + 1..].trim_start_matches(' ');
+            if !rest
+                .get(..7)
+                .is_some_and(|name| name.eq_ignore_ascii_case("noparse"))
+            {
+                continue;
+            }
+            if let (start, Ok((tag_end, name, false))) = self.chunk_start(0, j, j + 1) {
+                if start == j && name.eq_ignore_ascii_case("noparse") {
+                    let text_start = self.tag_chunk_end(tag_end, closing);
+                    let first_closing = find_closing_tag(&self.s[text_start..], "noparse", syntax);
+                    return (first_closing == Some(closing - text_start)).then_some(text_start);
+                }
+            }
+        }
+        None
+    }
+
+    /// Whether there's any closing tag for `name` after the current position, whether or not it
+    /// matches, which is much cheaper to check than a matching one.
+    fn has_closing_tag(&mut self, name_range: std::ops::Range<usize>) -> bool {
+        let syntax = self.syntax();
+        let i = self.i;
+        let s = self.s;
+        let name = &s[name_range.clone()];
+        let find_next = |from: usize| find_closing_tag(&s[from..], name, syntax).map(|j| from + j);
+        let lookahead = self.lookahead;
+        let closing_tag = match self
+            .closing_tags
+            .iter()
+            .position(|closing_tag| s[closing_tag.name.clone()].eq_ignore_ascii_case(name))
+        {
+            Some(index) => {
+                let closing_tag = &mut self.closing_tags[index];
+                // A tag that wasn't found before isn't found any further on either
+                if closing_tag.next.is_some_and(|next| next < i) {
+                    closing_tag.next = find_next(i);
+                }
+                closing_tag
+            }
+            None => {
+                self.closing_tags.push(ClosingTag {
+                    name: name_range,
+                    next: find_next(i),
+                    in_lookahead: None,
+                });
+                self.closing_tags.last_mut().unwrap()
+            }
+        };
+        closing_tag.next.is_some()
+            || *closing_tag
+                .in_lookahead
+                .get_or_insert_with(|| find_closing_tag(lookahead, name, syntax).is_some())
+    }
+}
+
+/// Finds the closing tag for `name`, ignoring ascii case. Lenient parsing also allows spaces
+/// after the `<` and `</`, e.g. `< / b>`.
+fn find_closing_tag(haystack: &str, name: &str, syntax: TagSyntax) -> Option<usize> {
+    if !syntax.lenient {
+        let mut closing_tag = String::with_capacity(3 + name.len());
+        closing_tag.push(syntax.open);
+        closing_tag.push('/');
+        closing_tag += name;
+        closing_tag.push(syntax.close);
+        return find_ignore_ascii_case(haystack, &closing_tag);
+    }
+
+    haystack
+        .match_indices(syntax.open)
+        .map(|(i, _)| i)
+        .find(|&i| {
+            let Some(rest) = haystack[i + 1..].trim_start_matches(' ').strip_prefix('/') else {
+                return false;
+            };
+            let rest = rest.trim_start_matches(' ');
+            rest.get(..name.len())
+                .is_some_and(|tag_name| tag_name.eq_ignore_ascii_case(name))
+                && rest[name.len()..].starts_with(syntax.close)
+        })
+}
+
+/// Like [`find_closing_tag`], but skips over nested tags with the same name, e.g. the inner
+/// `<size>` of `<size=1><size=2>x</size></size>`. `depth` is the number of nested tags that
+/// are still open at the end of the haystack, so the search can go on in the text after it.
+fn find_matching_closing_tag(
+    haystack: &str,
+    name: &str,
+    syntax: TagSyntax,
+    depth: &mut usize,
+) -> Option<usize> {
+    if name.eq_ignore_ascii_case("noparse") {
+        // Nothing is parsed inside of it, so it can't be nested either
+        return find_closing_tag(haystack, name, syntax);
+    }
+
+    fn strip_name<'s>(s: &'s str, name: &str) -> Option<&'s str> {
+        s.get(..name.len())
+            .filter(|prefix| prefix.eq_ignore_ascii_case(name))
+            .map(|_| &s[name.len()..])
+    }
+    let skip_spaces = |s| {
+        if syntax.lenient {
+            str::trim_start_matches(s, ' ')
+        } else {
+            s
+        }
+    };
+
+    let mut search_from = 0;
+    while let Some(i) = haystack[search_from..].find(syntax.open) {
+        let i = search_from + i;
+        search_from = i + syntax.open.len_utf8();
+        let rest = skip_spaces(&haystack[search_from..]);
+        if let Some(rest) = rest.strip_prefix('/') {
+            if strip_name(skip_spaces(rest), name)
+                .is_some_and(|rest| rest.starts_with(syntax.close))
+            {
+                if *depth == 0 {
+                    return Some(i);
+                }
+                *depth -= 1;
+            }
+        } else if let Some(rest) =
+            strip_name(rest, "noparse").filter(|rest| rest.starts_with(syntax.close))
+        {
+            // Tags inside of noparse are just text
+            let len = find_closing_tag(rest, "noparse", syntax)?;
+            search_from = offset_in(haystack, rest) + len;
+        } else if strip_name(rest, name)
+            .is_some_and(|rest| rest.starts_with(syntax.close) || rest.starts_with(['=', ' ']))
+        {
+            *depth += 1;
+        }
+    }
+    None
+}
+
+/// Tags that never have a closing tag in Unity's rich text, like `<br>` and `<sprite=1>`.
+pub const DEFAULT_VOID_TAGS: &[&str] = &["br", "sprite", "quad", "space", "pos", "page"];
+
+/// Tags that are usually left open in Unity's rich text, like `<alpha=#80>` which applies until
+/// the next `<alpha>` or the end of the text, and `<size=40>` which applies until the end.
+pub const DEFAULT_UNCLOSED_TAGS: &[&str] = &[
+    "align",
+    "alpha",
+    "color",
+    "cspace",
+    "font",
+    "font-weight",
+    "indent",
+    "line-height",
+    "line-indent",
+    "margin",
+    "margin-left",
+    "margin-right",
+    "mark",
+    "mspace",
+    "nobr",
+    "rotate",
+    "size",
+    "voffset",
+    "width",
+];
+
+fn is_void_tag(void_tags: &[&str], name: &str) -> bool {
+    void_tags.iter().any(|tag| tag.eq_ignore_ascii_case(name))
+}
+
+/// Whitespace that allows a line break, i.e. anything but the non-breaking spaces.
+fn is_breaking_whitespace(c: char) -> bool {
+    c.is_whitespace() && !matches!(c, '\u{a0}' | '\u{2007}' | '\u{202f}')
+}
+
+/// A chunk of [`isolate_tags_owned`], joining the chunks in order gives back the input.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
 pub struct IsolateTagsSection {
-    #[wasm_bindgen(getter_with_clone)]
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone))]
     pub chunk: String,
     pub is_tag: bool,
 }
 
-#[wasm_bindgen(js_name = isolateTags)]
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = isolateTags))]
 pub fn isolate_tags_owned(s: &str) -> Vec<IsolateTagsSection> {
+    isolate_tags_with_whitespace_owned(s, TagWhitespace::default())
+}
+
+/// Like [`isolate_tags_owned`], with the whitespace after tags going to `tag_whitespace`.
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = isolateTagsWithWhitespace))]
+pub fn isolate_tags_with_whitespace_owned(
+    s: &str,
+    tag_whitespace: TagWhitespace,
+) -> Vec<IsolateTagsSection> {
     IsolateTags::new(s)
+        .tag_whitespace(tag_whitespace)
         .map(|(chunk, is_tag)| IsolateTagsSection {
             chunk: chunk.to_owned(),
             is_tag,
@@ -119,78 +781,537 @@ pub fn isolate_tags_owned(s: &str) -> Vec<IsolateTagsSection> {
         .collect()
 }
 
-fn custom_word_separator(line: &str) -> Box<dyn Iterator<Item = Word<'_>> + '_> {
+/// Yields the sections of a string one at a time, like [`isolate_tags_owned`] but without
+/// collecting all of them at once.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+pub struct TagScanner {
+    s: String,
+    offset: usize,
+    in_noparse: bool,
+    /// The closing tags [`IsolateTags`] found so far, offsets in the whole string.
+    closing_tags: Vec<ClosingTag>,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl TagScanner {
+    #[cfg_attr(feature = "wasm", wasm_bindgen(constructor))]
+    pub fn new(s: String) -> Self {
+        Self {
+            s,
+            offset: 0,
+            in_noparse: false,
+            closing_tags: Vec::new(),
+        }
+    }
+
+    /// Returns the next section, or `undefined` once the whole string has been scanned.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name = next))]
+    pub fn next_section(&mut self) -> Option<IsolateTagsSection> {
+        self.next()
+    }
+
+    /// Byte offset of the next section in the string.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter))]
+    pub fn offset(&self) -> usize {
+        self.offset
+    }
+
+    /// Number of bytes that are left to scan.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter))]
+    pub fn remaining(&self) -> usize {
+        self.s.len() - self.offset
+    }
+}
+
+impl Iterator for TagScanner {
+    type Item = IsolateTagsSection;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        // Picks up where the last chunk ended with the state of the scan so far, so the closing
+        // tags that were already found aren't searched for again
+        let mut tags = IsolateTags::new(&self.s);
+        tags.set_start(self.offset);
+        tags.in_noparse = self.in_noparse;
+        tags.closing_tags = std::mem::take(&mut self.closing_tags);
+        let next = tags.next();
+        self.in_noparse = tags.in_noparse;
+        self.closing_tags = tags.closing_tags;
+        let (chunk, is_tag) = next?;
+        self.offset += chunk.len();
+        Some(IsolateTagsSection {
+            chunk: chunk.to_owned(),
+            is_tag,
+        })
+    }
+}
+
+/// Returns the plain text of the string with all tags removed, e.g. for search indexing.
+pub fn strip_tags(s: &str) -> Cow<'_, str> {
+    let mut segments = ParsedTags::new(s);
+    if !segments.any(|segment| matches!(segment, Segment::Tag { .. })) {
+        return Cow::Borrowed(s);
+    }
+
+    let mut text = String::with_capacity(s.len());
+    for segment in ParsedTags::new(s) {
+        if let Segment::Text(chunk) = segment {
+            text.push_str(chunk);
+        }
+    }
+    Cow::Owned(text)
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = stripTags))]
+pub fn strip_tags_owned(s: &str) -> String {
+    strip_tags(s).into_owned()
+}
+
+/// `rest` is the text after the line, which may close tags that are opened in it.
+fn custom_word_separator<'a>(
+    line: &'a str,
+    rest: &'a str,
+    options: &WrapOptions<'_>,
+    word_splitter: &WordSplitter,
+    sizes: &mut SizeStack,
+) -> Vec<Word<'a>> {
     // Isolate tags and other text (e.g. ['test', '<size=16>', 'hello world', '</size>'])
     // Iter returns str slice and whether to separate words in the section
     // We're only breaking the string on ascii chars, so it's safe to use the bytes
     // iterator and split them based on the index.
-    let mut isolate_iter = IsolateTags::new(line);
+    let mut words = Vec::new();
 
-    let mut unicode_break_iter: Box<dyn Iterator<Item = Word<'_>> + '_> =
-        Box::new(std::iter::empty());
-    Box::new(std::iter::from_fn(move || {
-        // Continue breaking current split
-        let break_res = unicode_break_iter.next();
-        if break_res.is_some() {
-            return break_res;
-        }
+    // Words inside of <nobr> are glued to the previous word, except for the first one
+    let mut nobr_depth = 0usize;
+    let mut nobr_start = false;
+    // Whether the next word has to stay on the same line as the glyph tag in front of it
+    let mut glue_to_tag = false;
+    // The text inside of <noparse> keeps its whitespace when collapsing
+    let mut in_noparse = false;
+    let collapse = options.collapse_whitespace && !options.preserve_spaces;
 
-        // Advance to next (non-empty) split
-        loop {
-            if let Some((next_section, is_tag)) = isolate_iter.next() {
-                if !is_tag {
-                    let mut iter = UnicodeBreakProperties.find_words(next_section);
-                    let break_res = iter.next();
-                    if break_res.is_some() {
-                        unicode_break_iter = iter;
-                        return break_res;
+    let sections = options.isolate_tags(line).with_lookahead(rest);
+    for (section, is_tag) in sections {
+        if is_tag {
+            // Everything after the closing bracket is whitespace
+            let tag_end = section.trim_end_matches(is_breaking_whitespace).len();
+            let tag = segment::parse_tag(&section[..tag_end]);
+            let mut whitespace = &section[tag_end..];
+            if collapse {
+                whitespace = collapse_whitespace(whitespace);
+            }
+            let mut word = Word::new(
+                &section[..tag_end],
+                whitespace,
+                "",
+                &options.measured_char_width(),
+            );
+            word.set_width(options.measure_tag(&tag));
+            word.is_tag = true;
+            if options.scale_size_tags {
+                word.scale_by(sizes.scale());
+            }
+            sizes.update(&tag);
+
+            let Segment::Tag { name, closing, .. } = tag else {
+                unreachable!("parse_tag always returns a tag");
+            };
+            if name.eq_ignore_ascii_case("noparse") {
+                in_noparse = !closing;
+            }
+            let is_nobr = name.eq_ignore_ascii_case("nobr");
+            if is_nobr && closing {
+                nobr_depth = nobr_depth.saturating_sub(1);
+            } else {
+                word.glue = nobr_depth > 0 && !nobr_start;
+                if is_nobr {
+                    nobr_depth += 1;
+                    nobr_start |= nobr_depth == 1;
+                }
+            }
+
+            // Formatting tags are left out when fitting and always end up on the same line as
+            // the word after them, glyph tags are glued to it since they take up space
+            if word.width() > 0.0 {
+                word.glue |= glue_to_tag;
+                glue_to_tag = options.glue_tags_to_words && !closing;
+            }
+            glue_to_tag &= word.whitespace.is_empty();
+            words.push(word);
+        } else {
+            // Only text is split, so hyphens never end up inside or next to a tag
+            let split = options
+                .line_break
+                .word_separator()
+                .find_words(section)
+                .flat_map(|word| split_at_nbsp(word, section, options.nbsp_is_breakable))
+                .map(|mut word| {
+                    if collapse && !in_noparse {
+                        word.whitespace = collapse_whitespace(word.whitespace);
                     }
+                    word
+                })
+                .flat_map(|word| Word::split(word, word_splitter, &options.measured_char_width()));
+            for mut word in split {
+                if let Some(ems) = sizes.mspace() {
+                    word.set_advance(ems * options.em_width);
                 } else {
-                    unicode_break_iter = Box::new(std::iter::empty());
-                    return Some(Word::from(next_section));
+                    if let Some(case) = sizes.case() {
+                        word.set_case(case, &options.measured_char_width());
+                    }
+                    if options.decode_entities && word.contains('&') {
+                        word.set_width(options.text_width(&word, sizes.case()));
+                    }
                 }
-            } else {
-                return None;
+                if options.scale_size_tags {
+                    word.scale_by(sizes.scale());
+                }
+                word.glue = (nobr_depth > 0 && !nobr_start) || glue_to_tag;
+                word.nobr = nobr_depth > 0;
+                nobr_start = false;
+                glue_to_tag = false;
+                words.push(word);
             }
         }
+    }
+    words
+}
+
+/// Splits a line into the words the wrapper fits into lines, tags are words of their own.
+///
+/// Words are split at break opportunities like spaces, hyphens and soft hyphens, and each
+/// word has the whitespace and penalty (e.g. a hyphen) that go after it if the line is broken
+/// there. The widths are available through [`Fragment`]. Long words aren't
+/// broken apart since that depends on the line width. The line shouldn't contain newlines,
+/// wrapping splits the text at them before splitting the words.
+///
+/// The break opportunities in the text between tags are the ones of the Unicode line breaking
+/// algorithm (UAX #14) by default, so text without spaces is broken too, e.g. after the `/` of
+/// a path or between CJK characters, see [`WrapOptions::line_break`]. Breaks at hyphens are
+/// left to the word splitter, which also hyphenates words when the `hyphenation` feature is
+/// enabled.
+pub fn split_words(line: &str) -> Vec<Word<'_>> {
+    split_words_with_options(line, &WrapOptions::new(0))
+}
+
+pub fn split_words_with_options<'a>(line: &'a str, options: &WrapOptions<'_>) -> Vec<Word<'a>> {
+    let word_splitter = word_splitter(options);
+    custom_word_separator(line, "", options, &word_splitter, &mut SizeStack::default())
+}
+
+/// Wraps words from [`split_words`] into lines of `line_widths`, the last width is used for all
+/// the remaining lines. The words only have to be split once to try out several widths this
+/// way, e.g. when fitting text into a box.
+///
+/// Formatting tags don't take up space and glued words stay together like when wrapping the
+/// text. Words that are wider than the line aren't broken apart, neither are lines broken at
+/// `<br>` tags, since both are done before the words are wrapped. Each line is a slice of the
+/// words, without any widths they all end up on a single line.
+pub fn wrap_words<'a, 'b>(
+    words: &'b [Word<'a>],
+    line_widths: &[f64],
+    penalties: &Penalties,
+) -> Vec<&'b [Word<'a>]> {
+    wrap_words_with_options(
+        words,
+        line_widths,
+        &WrapOptions::new(0).penalties(*penalties),
+    )
+}
+
+/// Like [`wrap_words`], but uses the wrap algorithm and the other options that decide where
+/// the lines are broken, like [`WrapOptions::max_words_per_line`]. The options that change how
+/// the words are measured only apply when splitting them.
+pub fn wrap_words_with_options<'a, 'b>(
+    words: &'b [Word<'a>],
+    line_widths: &[f64],
+    options: &WrapOptions<'_>,
+) -> Vec<&'b [Word<'a>]> {
+    if line_widths.is_empty() || words.is_empty() {
+        return vec![words];
+    }
+    custom_wrap_algorithm(words, line_widths, options).unwrap_or_else(|_| {
+        // Like when wrapping text, lines that optimal fit fails on are wrapped with first fit
+        let options = options.clone().wrap_algorithm(WrapAlgorithmKind::FirstFit);
+        custom_wrap_algorithm(words, line_widths, &options).unwrap_or_else(|_| vec![words])
+    })
+}
+
+/// Splits the word after the no-break spaces in it if they're `breakable`, the spaces become
+/// the whitespace of the part in front of them. `section` is the text the word is a slice of.
+fn split_at_nbsp<'a>(
+    word: textwrap::core::Word<'a>,
+    section: &'a str,
+    breakable: bool,
+) -> impl Iterator<Item = textwrap::core::Word<'a>> {
+    const NBSP: char = '\u{a0}';
+    let mut rest = Some(word);
+    std::iter::from_fn(move || {
+        let word = rest.take()?;
+        let Some(start) = word.word.find(NBSP).filter(|_| breakable) else {
+            return Some(word);
+        };
+        let end = word.word.len() - word.word[start..].trim_start_matches(NBSP).len();
+        let mut part = textwrap::core::Word::from(&word.word[..start]);
+        if end == word.word.len() {
+            // The spaces run into the whitespace after the word
+            let offset = offset_in(section, word.word);
+            part.whitespace = &section[offset + start..offset + end + word.whitespace.len()];
+            part.penalty = word.penalty;
+        } else {
+            part.whitespace = &word.word[start..end];
+            let mut next = textwrap::core::Word::from(&word.word[end..]);
+            next.whitespace = word.whitespace;
+            next.penalty = word.penalty;
+            rest = Some(next);
+        }
+        Some(part)
+    })
+}
+
+/// Shortens a run of whitespace to a single char, preferably a space.
+fn collapse_whitespace(whitespace: &str) -> &str {
+    match whitespace.char_indices().nth(1) {
+        None => whitespace,
+        Some((second, _)) => match whitespace.find(' ') {
+            Some(i) => &whitespace[i..i + 1],
+            None => &whitespace[..second],
+        },
+    }
+}
+
+/// Line breaking algorithm used to fit the words into lines.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum WrapAlgorithmKind {
+    /// Minimize the raggedness of the lines, see [`wrap_algorithms::wrap_optimal_fit`].
+    #[default]
+    OptimalFit,
+    /// Greedily fill each line, see [`wrap_algorithms::wrap_first_fit`].
+    FirstFit,
+}
+
+/// What happens to a word that is wider than the line when it isn't broken apart, see
+/// [`WrapOptions::break_long_words`].
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Overflow {
+    /// Let the word stick out of the line.
+    #[default]
+    Allow,
+    /// Cut the word off and end it with [`WrapOptions::ellipsis`].
+    Truncate,
+    /// Fail with [`WrapError::WordTooWide`] in [`try_wrap_text`], functions that can't fail let
+    /// the word stick out like [`Overflow::Allow`].
+    Error,
+}
+
+/// Where the text between tags can be broken, see [`WrapOptions::line_break`]. Hyphens are
+/// break opportunities either way, they're left to the word splitter.
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum LineBreakKind {
+    /// At the break opportunities of the Unicode line breaking algorithm (UAX #14), so text
+    /// without spaces is broken too, e.g. after the `/` of a path or between CJK characters.
+    #[default]
+    Uax14,
+    /// Only at whitespace, words that don't fit are broken apart like any other long word.
+    Words,
+}
+
+impl LineBreakKind {
+    fn word_separator(self) -> WordSeparator {
+        match self {
+            LineBreakKind::Words => WordSeparator::Custom(find_words_at_whitespace),
+            LineBreakKind::Uax14 => WordSeparator::UnicodeBreakProperties,
+        }
+    }
+}
+
+/// Splits the line after every run of breaking whitespace, including non-ascii whitespace like
+/// U+3000 that [`WordSeparator::AsciiSpace`] doesn't know about.
+fn find_words_at_whitespace(line: &str) -> Box<dyn Iterator<Item = textwrap::core::Word<'_>> + '_> {
+    let mut start = 0;
+    Box::new(std::iter::from_fn(move || {
+        if start == line.len() {
+            return None;
+        }
+        let rest = &line[start..];
+        let word_len = rest.find(is_breaking_whitespace).unwrap_or(rest.len());
+        let whitespace = &rest[word_len..];
+        let whitespace_len =
+            whitespace.len() - whitespace.trim_start_matches(is_breaking_whitespace).len();
+        let mut word = textwrap::core::Word::from(&rest[..word_len]);
+        word.whitespace = &whitespace[..whitespace_len];
+        start += word_len + whitespace_len;
+        Some(word)
     }))
 }
 
+fn wrap_fragments<'a, T: Fragment>(
+    fragments: &'a [T],
+    line_widths: &[f64],
+    options: &WrapOptions<'_>,
+) -> Result<Vec<&'a [T]>, WrapError> {
+    match options.measured_wrap_algorithm() {
+        WrapAlgorithmKind::OptimalFit => {
+            let lines =
+                wrap_algorithms::wrap_optimal_fit(fragments, line_widths, &options.penalties)
+                    .map_err(|_| WrapError::Overflow)?;
+            Ok(if options.prefer_earlier_breaks {
+                optimal_fit::prefer_earlier_breaks(
+                    fragments,
+                    lines,
+                    line_widths,
+                    &options.penalties,
+                )
+            } else {
+                lines
+            })
+        }
+        WrapAlgorithmKind::FirstFit => Ok(wrap_algorithms::wrap_first_fit(fragments, line_widths)),
+    }
+}
+
+/// A run of glued words that has to stay on the same line.
+#[derive(Debug)]
+struct Cluster {
+    width: f64,
+    whitespace_width: f64,
+    penalty_width: f64,
+    len: usize,
+    /// Number of words that start in the cluster, for [`WrapOptions::max_words_per_line`].
+    words: usize,
+    /// Whether the cluster starts with the rest of a word that was split, which still counts as
+    /// a word at the start of a line.
+    continues_word: bool,
+}
+
+impl Fragment for Cluster {
+    fn width(&self) -> f64 {
+        self.width
+    }
+
+    fn whitespace_width(&self) -> f64 {
+        self.whitespace_width
+    }
+
+    fn penalty_width(&self) -> f64 {
+        self.penalty_width
+    }
+}
+
+/// Wraps the fragments while keeping glued words together, returns the number of fragments on
+/// each line. `word_starts` tells which of the fragments start a new word, as opposed to being
+/// the rest of a split word.
+fn wrap_clustered(
+    fragments: &[Word<'_>],
+    word_starts: &[bool],
+    line_widths: &[f64],
+    options: &WrapOptions<'_>,
+) -> Result<Vec<usize>, WrapError> {
+    let mut clusters: Vec<Cluster> = Vec::with_capacity(fragments.len());
+    for (fragment, &starts_word) in fragments.iter().zip(word_starts) {
+        let continues_word = counts_as_word(fragment, options) && !starts_word;
+        match clusters.last_mut() {
+            Some(cluster) if fragment.glue => {
+                cluster.width += cluster.whitespace_width + fragment.width();
+                cluster.whitespace_width = fragment.whitespace_width();
+                cluster.penalty_width = fragment.penalty_width();
+                cluster.len += 1;
+                cluster.words += usize::from(starts_word);
+            }
+            _ => clusters.push(Cluster {
+                width: fragment.width(),
+                whitespace_width: fragment.whitespace_width(),
+                penalty_width: fragment.penalty_width(),
+                len: 1,
+                words: usize::from(starts_word),
+                continues_word,
+            }),
+        }
+    }
+
+    let Some(max_words) = options.max_words_per_line.filter(|_| !clusters.is_empty()) else {
+        return Ok(wrap_fragments(&clusters, line_widths, options)?
+            .iter()
+            .map(|line| line.iter().map(|cluster| cluster.len).sum())
+            .collect());
+    };
+
+    // Cut the first line with too many words short and wrap the rest again from there
+    let mut line_lens = Vec::new();
+    let mut start = 0;
+    while start < clusters.len() {
+        let widths = &line_widths[line_lens.len().min(line_widths.len() - 1)..];
+        for line in wrap_fragments(&clusters[start..], widths, options)? {
+            let mut words = 0;
+            let kept = line
+                .iter()
+                .enumerate()
+                .position(|(i, cluster)| {
+                    words += cluster.words + usize::from(i == 0 && cluster.continues_word);
+                    words > max_words && i > 0
+                })
+                .unwrap_or(line.len());
+            line_lens.push(line[..kept].iter().map(|cluster| cluster.len).sum());
+            start += kept;
+            if kept < line.len() {
+                break;
+            }
+        }
+    }
+    Ok(line_lens)
+}
+
+/// Whether the fragment counts towards [`WrapOptions::max_words_per_line`].
+fn counts_as_word(fragment: &Word<'_>, options: &WrapOptions<'_>) -> bool {
+    !fragment.is_tag || options.count_tags_as_words
+}
+
 fn custom_wrap_algorithm<'a, 'b>(
     words: &'b [Word<'a>],
-    line_widths: &'b [usize],
-) -> Vec<&'b [Word<'a>]> {
+    line_widths: &[f64],
+    options: &WrapOptions<'_>,
+) -> Result<Vec<&'b [Word<'a>]>, WrapError> {
     // Create intermediate buffer that doesn't contain formatting tags
-    let mut clean_fragments = Vec::with_capacity(words.len());
+    // Tags with a visual width (e.g. sprites) are kept since they take up space on the line
+    let mut clean_fragments: Vec<Word<'a>> = Vec::with_capacity(words.len());
     let mut removed_indices = Vec::with_capacity(words.len());
     let mut remove_offset = 0;
+    let mut has_glue = false;
+    let mut pending_glue = false;
+    // Whitespace after a word can also be after the formatting tags following it
+    let mut word_starts = Vec::with_capacity(words.len());
+    let mut after_whitespace = true;
     for (i, word) in words.iter().enumerate() {
-        if word.starts_with("<") && word.ends_with(">") {
+        has_glue |= word.glue;
+        if word.is_tag && word.width() == 0.0 {
             removed_indices.push(i - remove_offset);
             remove_offset += 1;
+            // The break before the next fragment is also a break before this tag
+            pending_glue |= word.glue;
+            // The whitespace after the tag is still drawn, so it's measured after the word before
+            if let Some(fragment) = clean_fragments.last_mut() {
+                fragment.add_whitespace_width(word.whitespace_width());
+            }
+            after_whitespace |= !word.whitespace.is_empty();
             continue;
         }
-        clean_fragments.push(words[i]);
+        let mut fragment = words[i];
+        fragment.glue |= pending_glue;
+        pending_glue = false;
+        // Words right after a glyph tag that doesn't count as a word still count themselves
+        let counts = counts_as_word(word, options);
+        word_starts.push(counts && after_whitespace);
+        after_whitespace = !counts || !word.whitespace.is_empty();
+        clean_fragments.push(fragment);
     }
 
     // quick escape!!!11
-    let f64_line_widths = line_widths.iter().map(|w| *w as f64).collect::<Vec<_>>();
-    if remove_offset == 0 {
-        return wrap_algorithms::wrap_optimal_fit(
-            words,
-            &f64_line_widths,
-            &wrap_algorithms::Penalties::new(),
-        )
-        .unwrap();
+    if remove_offset == 0 && !has_glue && options.max_words_per_line.is_none() {
+        return wrap_fragments(words, line_widths, options)
+            .map(|lines| kinsoku::apply(words, lines, options));
     }
 
     // Wrap without formatting tags
-    let wrapped = wrap_algorithms::wrap_optimal_fit(
-        &clean_fragments,
-        &f64_line_widths,
-        &wrap_algorithms::Penalties::new(),
-    )
-    .unwrap();
+    let wrapped = wrap_clustered(&clean_fragments, &word_starts, line_widths, options)?;
 
     // Create results with formatting tags added back
     // Note: The break word option doesn't really affect the extra long lines since
@@ -199,25 +1320,29 @@ fn custom_wrap_algorithm<'a, 'b>(
     let mut start = 0;
     let mut clean_start = 0;
     let mut removed_indices_i = 0;
-    for (i, line) in wrapped.iter().enumerate() {
+    for (i, line_len) in wrapped.iter().enumerate() {
         let mut end: usize;
         if i == wrapped.len() - 1 {
             end = words.len();
         } else {
-            let clean_end = clean_start + line.len();
-            end = start + line.len();
-            loop {
-                let Some(index) = removed_indices.get(removed_indices_i) else {
+            let clean_end = clean_start + line_len;
+            end = start + line_len;
+            // Lines are contiguous, so every removed tag before the end of this line's clean
+            // words belongs to it, even if it somehow wasn't claimed by a previous line
+            while let Some(&index) = removed_indices.get(removed_indices_i) {
+                if index >= clean_end {
                     break;
-                };
-                if *index >= clean_start {
-                    if *index < clean_end {
-                        end += 1;
-                        removed_indices_i += 1;
-                    } else {
-                        break;
-                    }
                 }
+                end += 1;
+                removed_indices_i += 1;
+            }
+            // Tags right at the break go to the next line along with the word after them, except
+            // for closing tags which still close on this line
+            while removed_indices.get(removed_indices_i) == Some(&clean_end)
+                && is_closing_tag(&words[end])
+            {
+                end += 1;
+                removed_indices_i += 1;
             }
             clean_start = clean_end;
         }
@@ -225,22 +1350,1839 @@ fn custom_wrap_algorithm<'a, 'b>(
         lines.push(&words[start..end]);
         start = end;
     }
-    lines
+    Ok(kinsoku::apply(words, lines, options))
 }
 
-pub fn wrap_text(
-    string: &str,
-    base_line_width: i32,
-    line_width_multiplier: f32,
-) -> Vec<Cow<'_, str>> {
-    let line_width = (base_line_width as f32 * line_width_multiplier).round() as usize;
-    let options = textwrap::Options::new(line_width)
-        .word_separator(textwrap::WordSeparator::Custom(custom_word_separator))
-        .wrap_algorithm(textwrap::WrapAlgorithm::Custom(custom_wrap_algorithm));
-    textwrap::wrap(string, &options)
+/// Whether the word is a `<br>` or `<br/>` tag, which forces a line break.
+fn is_line_break(word: &Word<'_>) -> bool {
+    word.is_tag
+        && matches!(segment::parse_tag(word), Segment::Tag { name, closing: false, .. }
+            if name.eq_ignore_ascii_case("br"))
+}
+
+fn is_closing_tag(word: &Word<'_>) -> bool {
+    word.is_tag && matches!(segment::parse_tag(word), Segment::Tag { closing: true, .. })
+}
+
+/// Whether the tag word is left out of the output.
+fn is_hidden(word: &Word<'_>, options: &WrapOptions<'_>) -> bool {
+    word.is_tag && options.remove_noparse_tags && tag_name(word).eq_ignore_ascii_case("noparse")
+}
+
+/// Byte offset of `sub` in `s`, `sub` must be a slice of `s`.
+fn offset_in(s: &str, sub: &str) -> usize {
+    sub.as_ptr() as usize - s.as_ptr() as usize
+}
+
+/// How many times a paragraph is wrapped again when the `<indent>` and `<margin>` tags change the
+/// widths of its lines, see [`LineWrapper::wrap_line`]. Usually the second pass settles.
+const MAX_INDENT_PASSES: usize = 4;
+
+/// Width taken up by the `<indent>` and `<margin>` tags that are open, out of a line of `width`.
+/// Only the innermost tag for each side counts, like in Unity.
+fn indentation(open_tags: &[&str], width: f64, options: &WrapOptions<'_>) -> f64 {
+    let mut left = None;
+    let mut right = None;
+    for &tag in open_tags {
+        let tag = segment::parse_tag(tag);
+        let Segment::Tag { name, .. } = tag else {
+            continue;
+        };
+        let Some(length) = tag.value().and_then(Length::parse) else {
+            continue;
+        };
+        let columns = length.columns(width, options.em_width);
+        match name.to_ascii_lowercase().as_str() {
+            "indent" | "margin-left" => left = Some(columns),
+            "margin-right" => right = Some(columns),
+            "margin" => (left, right) = (Some(columns), Some(columns)),
+            _ => {}
+        }
+    }
+    left.unwrap_or_default() + right.unwrap_or_default()
+}
+
+/// Applies a tag word to the stack of currently open tags.
+fn update_open_tags<'a>(open_tags: &mut Vec<&'a str>, tag: &'a str, options: &WrapOptions<'_>) {
+    let Segment::Tag {
+        name,
+        closing,
+        self_closing,
+        ..
+    } = segment::parse_tag(tag)
+    else {
+        return;
+    };
+
+    if closing {
+        // Pop the nearest matching tag, closing tags don't carry the attributes
+        if let Some(pos) = open_tags
+            .iter()
+            .rposition(|t| tag_name(t).eq_ignore_ascii_case(name))
+        {
+            open_tags.remove(pos);
+        }
+    } else if !self_closing && !is_void_tag(options.void_tags, name) {
+        open_tags.push(tag);
+    }
+}
+
+fn tag_name(tag: &str) -> &str {
+    match segment::parse_tag(tag) {
+        Segment::Tag { name, .. } => name,
+        Segment::Text(_) => "",
+    }
+}
+
+/// The splitter used to break up words, this is where hyphenation happens.
+#[cfg_attr(not(feature = "hyphenation"), allow(unused_variables))]
+fn word_splitter(options: &WrapOptions<'_>) -> WordSplitter {
+    #[cfg(feature = "hyphenation")]
+    if let Some(dictionary) = options.hyphenate.and_then(hyphenation_dictionary) {
+        return WordSplitter::Hyphenation(dictionary);
+    }
+    WordSplitter::HyphenSplitter
+}
+
+/// Loads the embedded dictionary for the language, dictionaries are cached since loading them
+/// is slow.
+#[cfg(feature = "hyphenation")]
+fn hyphenation_dictionary(language: Language) -> Option<hyphenation::Standard> {
+    use hyphenation::Load;
+    use std::{cell::RefCell, collections::HashMap};
+
+    thread_local! {
+        static DICTIONARIES: RefCell<HashMap<Language, Option<hyphenation::Standard>>> =
+            RefCell::new(HashMap::new());
+    }
+    DICTIONARIES.with_borrow_mut(|dictionaries| {
+        dictionaries
+            .entry(language)
+            .or_insert_with(|| hyphenation::Standard::from_embedded(language).ok())
+            .clone()
+    })
+}
+
+/// Options for [`wrap_text_with_options`].
+#[derive(Debug, Clone)]
+pub struct WrapOptions<'a> {
+    pub base_line_width: i32,
+    pub line_width_multiplier: f32,
+    /// Widths of the first lines of the output for non-rectangular text areas, the last one is
+    /// used for all the remaining lines. Replaces [`Self::base_line_width`] unless empty, the
+    /// multiplier still applies.
+    pub line_widths: Vec<usize>,
+    /// Lines are at least this wide, 1 by default. A base width of zero or less, or a
+    /// multiplier that rounds the width down to zero would otherwise leave no room at all.
+    pub min_line_width: usize,
+    /// Added to the start of every output line, before the reopened tags, e.g. `> ` for a
+    /// quote. Its width is taken off the line width, tags in it are measured like in the text.
+    /// Only the lines of [`wrap_text`] and friends get it, not spans.
+    pub line_prefix: &'a str,
+    /// Added to the end of every output line, after the closed tags. Its width is taken off the
+    /// line width too.
+    pub line_suffix: &'a str,
+    /// Added to the first line of each paragraph and of the text after each `<br>`, after
+    /// [`Self::line_prefix`], like textwrap's `initial_indent`. Its width is taken off the
+    /// width of those lines.
+    pub initial_indent: &'a str,
+    /// Added to the other lines like [`Self::initial_indent`], i.e. the lines that start at a
+    /// wrap point.
+    pub subsequent_indent: &'a str,
+    /// Terminate each line with the closing tags of everything that is still open at the end of
+    /// it, so that every line is self-contained rich text.
+    pub close_open_tags_per_line: bool,
+    /// Keep glyph tags like `<sprite>` on the same line as the word right after them, so a line
+    /// never ends with a dangling tag. Opening formatting tags always move to the next line
+    /// along with the word after them, while closing tags stay at the end of the line.
+    pub glue_tags_to_words: bool,
+    /// Split words that are wider than the line at char boundaries instead of letting them
+    /// overflow, like textwrap's `break_words`. Tags are never split. Enabled by default.
+    pub break_long_words: bool,
+    /// What to do with the words that are wider than the line if they aren't broken apart,
+    /// they overflow it by default.
+    pub overflow: Overflow,
+    /// Maximum number of words on a line, a line is broken after this many words even if more
+    /// would fit. Glued words are never separated, so a line can still have more of them.
+    pub max_words_per_line: Option<usize>,
+    /// Count glyph tags like `<sprite>` as words for [`Self::max_words_per_line`]. Formatting
+    /// tags never count since they don't take up any space.
+    pub count_tags_as_words: bool,
+    /// Maximum number of lines, the text is truncated after the last line with [`Self::ellipsis`].
+    /// Words at the end of the last line are left out until the ellipsis fits after them, the
+    /// tags that are still open are closed after it.
+    pub max_lines: Option<usize>,
+    /// Appended to the last line when the text is truncated, `…` by default. Also ends the
+    /// words that are cut off by [`Overflow::Truncate`].
+    pub ellipsis: &'a str,
+    /// Leave the `<noparse>` and `</noparse>` tags out of the output, their contents are always
+    /// treated as plain text.
+    pub remove_noparse_tags: bool,
+    /// Allow spaces before tag names like `< b >` and `</ b>`, see [`IsolateTags::lenient`].
+    pub lenient_tags: bool,
+    /// The ascii characters that start and end a tag, `('<', '>')` by default, see
+    /// [`IsolateTags::with_delimiters`].
+    pub tag_delimiters: (char, char),
+    /// Names of the tags that don't have a closing tag, see [`IsolateTags::void_tags`].
+    pub void_tags: &'a [&'a str],
+    /// Names of the tags that apply until the end of the text if they aren't closed, see
+    /// [`IsolateTags::unclosed_tags`].
+    pub unclosed_tags: &'a [&'a str],
+    /// Keep the whitespace after each word as part of it, so lines also keep their trailing
+    /// whitespace and joining them reproduces the original spacing.
+    ///
+    /// The whitespace then counts towards the width of the line, so a line fits a little less
+    /// text, and the optimal fit algorithm sees the trailing whitespace as part of the line
+    /// rather than as free space at the end of it. Takes precedence over
+    /// [`Self::collapse_whitespace`], the whitespace is kept as is.
+    pub preserve_spaces: bool,
+    /// Shorten runs of whitespace between words to a single space, e.g. double spaces in
+    /// translated text. Tabs are only whitespace once they're expanded with
+    /// [`Self::tab_width`]. The text inside of `<noparse>` is left alone, and so is everything
+    /// when [`Self::preserve_spaces`] is set.
+    pub collapse_whitespace: bool,
+    /// Allow line breaks at no-break spaces (U+00A0), which keep the words on both sides of
+    /// them together by default. The narrow and figure no-break spaces still never break.
+    pub nbsp_is_breakable: bool,
+    /// How the width of non-tag characters is measured.
+    pub char_width: CharWidth<'a>,
+    /// Measure the entities `&lt;`, `&gt;` and `&amp;` as the single character they stand for,
+    /// for text that escapes literal brackets. The output still contains the entities. They
+    /// never start a tag either way, only an actual `<` does.
+    pub decode_entities: bool,
+    /// How much space tags like `<sprite>` take up on the line.
+    pub tag_width: TagWidth<'a>,
+    /// Width of an em in columns, for horizontal spacing like `<space=2.5em>` and
+    /// `<indent=2em>`, and the advance of the chars inside of `<mspace=1em>`. 1 by default.
+    /// Spaces in other units are measured with [`Self::tag_width`] like any other tag.
+    ///
+    /// `<indent>` and `<margin>` tags in ems or percentages make the lines narrower. The
+    /// indentation of every line is set by the tags open at its start, so a tag opened or
+    /// closed further in applies from the next line on.
+    pub em_width: f64,
+    /// Scale the width of the text inside of `<size=...%>` tags by the percentage, other sizes
+    /// are measured like regular text.
+    pub scale_size_tags: bool,
+    pub wrap_algorithm: WrapAlgorithmKind,
+    /// Where the text can be broken, at the Unicode line break opportunities by default.
+    pub line_break: LineBreakKind,
+    /// Wrap to lines of exactly this many chars for fixed-width displays, instead of measuring
+    /// the text. Every char is a column and tags don't count, the line is filled greedily with
+    /// [`WrapAlgorithmKind::FirstFit`]. Replaces [`Self::base_line_width`],
+    /// [`Self::line_widths`] and the multiplier, as well as [`Self::char_width`] and
+    /// [`Self::tag_width`].
+    pub fixed_columns: Option<usize>,
+    /// Return the lines of right-to-left text like Arabic or Hebrew in visual order, for
+    /// renderers without bidi support. The text is still wrapped in logical order, then each
+    /// line is reversed and its tags are closed at the end so it's self-contained. Runs of
+    /// left-to-right text like numbers keep their order, but this isn't the full bidi
+    /// algorithm. Only the lines of [`wrap_text`] and friends are reordered, not spans or
+    /// breaks.
+    pub rtl: bool,
+    /// Move the line breaks so that lines don't start with closing brackets, punctuation or
+    /// small kana, or end with opening brackets, like Japanese line breaking rules (kinsoku
+    /// shori). [`LineBreakKind::Uax14`] already keeps most of these together, this also
+    /// catches the ones next to tags and in words broken apart. A character that can't start a
+    /// line is pulled onto the previous one even if it overflows it a little.
+    pub kinsoku: bool,
+    /// The characters that [`Self::kinsoku`] keeps away from the start and end of lines.
+    pub kinsoku_chars: KinsokuChars<'a>,
+    /// Hyphenate words using the dictionary of this language, words are only split at hyphens
+    /// by default. Only en-us is embedded unless the `hyphenation-all` feature is enabled,
+    /// other languages aren't hyphenated without it.
+    #[cfg(feature = "hyphenation")]
+    pub hyphenate: Option<Language>,
+    /// Expand tabs to the next multiple of this many columns before wrapping, tags don't count
+    /// towards the column. Tabs are left as is by default.
+    pub tab_width: Option<usize>,
+    /// Characters removed before wrapping, only the byte order mark by default. Stray control
+    /// characters mess up the width of the lines.
+    pub strip_chars: StripChars<'a>,
+    /// Costs used by [`WrapAlgorithmKind::OptimalFit`], e.g. to discourage short last lines.
+    pub penalties: Penalties,
+    /// Move the breaks chosen by [`WrapAlgorithmKind::OptimalFit`] as far back as they can go
+    /// without making the lines any worse. Lines that are equally good can be picked
+    /// differently by different versions of the algorithm, this settles the ties the same way
+    /// every time, e.g. for snapshot tests. Breaks are moved one word at a time starting with
+    /// the first, using the costs from [`Self::penalties`].
+    pub prefer_earlier_breaks: bool,
+}
+
+impl<'a> WrapOptions<'a> {
+    /// Creates the default options for lines of `base_line_width`, the other options can be
+    /// changed with the builder methods, e.g.
+    /// `WrapOptions::new(40).multiplier(1.2).max_lines(3)`.
+    pub fn new(base_line_width: i32) -> Self {
+        Self {
+            base_line_width,
+            line_width_multiplier: 1.0,
+            line_widths: Vec::new(),
+            min_line_width: 1,
+            line_prefix: "",
+            line_suffix: "",
+            initial_indent: "",
+            subsequent_indent: "",
+            close_open_tags_per_line: false,
+            glue_tags_to_words: false,
+            break_long_words: true,
+            overflow: Overflow::default(),
+            max_words_per_line: None,
+            count_tags_as_words: false,
+            max_lines: None,
+            ellipsis: "…",
+            remove_noparse_tags: false,
+            lenient_tags: false,
+            tag_delimiters: ('<', '>'),
+            void_tags: DEFAULT_VOID_TAGS,
+            unclosed_tags: DEFAULT_UNCLOSED_TAGS,
+            preserve_spaces: false,
+            collapse_whitespace: false,
+            nbsp_is_breakable: false,
+            char_width: CharWidth::default(),
+            decode_entities: false,
+            tag_width: TagWidth::default(),
+            em_width: 1.0,
+            scale_size_tags: false,
+            wrap_algorithm: WrapAlgorithmKind::default(),
+            line_break: LineBreakKind::default(),
+            fixed_columns: None,
+            rtl: false,
+            kinsoku: false,
+            kinsoku_chars: KinsokuChars::default(),
+            #[cfg(feature = "hyphenation")]
+            hyphenate: None,
+            tab_width: None,
+            strip_chars: StripChars::default(),
+            penalties: Penalties::new(),
+            prefer_earlier_breaks: false,
+        }
+    }
+
+    /// Creates the options for lines of `pixel_width` pixels, measuring the characters with
+    /// the advances of `metrics`. Every other width is in pixels too, e.g.
+    /// [`Self::em_width`] should be set to the font size for `<space=1em>` to be an em wide.
+    pub fn with_font_metrics(metrics: &'a FontMetrics, pixel_width: f32) -> Self {
+        // The width is kept as the multiplier so it isn't rounded to whole pixels
+        Self::new(1)
+            .multiplier(pixel_width)
+            .char_width(CharWidth::Font(metrics))
+    }
+
+    /// Creates the options for lines that take up `percent` of a container that is
+    /// `container_width` wide, e.g. `WrapOptions::from_container(80, 50.0)` for lines of 40.
+    /// The percentage is kept as the multiplier, so a fractional width isn't rounded either.
+    pub fn from_container(container_width: i32, percent: f32) -> Self {
+        Self::new(container_width).multiplier(percent / 100.0)
+    }
+
+    pub fn multiplier(mut self, line_width_multiplier: f32) -> Self {
+        self.line_width_multiplier = line_width_multiplier;
+        self
+    }
+
+    pub fn line_widths(mut self, line_widths: Vec<usize>) -> Self {
+        self.line_widths = line_widths;
+        self
+    }
+
+    pub fn min_line_width(mut self, min_line_width: usize) -> Self {
+        self.min_line_width = min_line_width;
+        self
+    }
+
+    pub fn line_prefix(mut self, line_prefix: &'a str) -> Self {
+        self.line_prefix = line_prefix;
+        self
+    }
+
+    pub fn line_suffix(mut self, line_suffix: &'a str) -> Self {
+        self.line_suffix = line_suffix;
+        self
+    }
+
+    pub fn initial_indent(mut self, initial_indent: &'a str) -> Self {
+        self.initial_indent = initial_indent;
+        self
+    }
+
+    pub fn subsequent_indent(mut self, subsequent_indent: &'a str) -> Self {
+        self.subsequent_indent = subsequent_indent;
+        self
+    }
+
+    pub fn close_open_tags_per_line(mut self, close_open_tags_per_line: bool) -> Self {
+        self.close_open_tags_per_line = close_open_tags_per_line;
+        self
+    }
+
+    pub fn glue_tags_to_words(mut self, glue_tags_to_words: bool) -> Self {
+        self.glue_tags_to_words = glue_tags_to_words;
+        self
+    }
+
+    pub fn break_long_words(mut self, break_long_words: bool) -> Self {
+        self.break_long_words = break_long_words;
+        self
+    }
+
+    pub fn overflow(mut self, overflow: Overflow) -> Self {
+        self.overflow = overflow;
+        self
+    }
+
+    pub fn max_words_per_line(mut self, max_words_per_line: usize) -> Self {
+        self.max_words_per_line = Some(max_words_per_line);
+        self
+    }
+
+    pub fn count_tags_as_words(mut self, count_tags_as_words: bool) -> Self {
+        self.count_tags_as_words = count_tags_as_words;
+        self
+    }
+
+    pub fn max_lines(mut self, max_lines: usize) -> Self {
+        self.max_lines = Some(max_lines);
+        self
+    }
+
+    pub fn ellipsis(mut self, ellipsis: &'a str) -> Self {
+        self.ellipsis = ellipsis;
+        self
+    }
+
+    pub fn remove_noparse_tags(mut self, remove_noparse_tags: bool) -> Self {
+        self.remove_noparse_tags = remove_noparse_tags;
+        self
+    }
+
+    pub fn lenient_tags(mut self, lenient_tags: bool) -> Self {
+        self.lenient_tags = lenient_tags;
+        self
+    }
+
+    pub fn tag_delimiters(mut self, open: char, close: char) -> Self {
+        self.tag_delimiters = (open, close);
+        self
+    }
+
+    pub fn void_tags(mut self, void_tags: &'a [&'a str]) -> Self {
+        self.void_tags = void_tags;
+        self
+    }
+
+    pub fn unclosed_tags(mut self, unclosed_tags: &'a [&'a str]) -> Self {
+        self.unclosed_tags = unclosed_tags;
+        self
+    }
+
+    pub fn preserve_spaces(mut self, preserve_spaces: bool) -> Self {
+        self.preserve_spaces = preserve_spaces;
+        self
+    }
+
+    pub fn collapse_whitespace(mut self, collapse_whitespace: bool) -> Self {
+        self.collapse_whitespace = collapse_whitespace;
+        self
+    }
+
+    pub fn nbsp_is_breakable(mut self, nbsp_is_breakable: bool) -> Self {
+        self.nbsp_is_breakable = nbsp_is_breakable;
+        self
+    }
+
+    pub fn char_width(mut self, char_width: CharWidth<'a>) -> Self {
+        self.char_width = char_width;
+        self
+    }
+
+    pub fn decode_entities(mut self, decode_entities: bool) -> Self {
+        self.decode_entities = decode_entities;
+        self
+    }
+
+    pub fn tag_width(mut self, tag_width: TagWidth<'a>) -> Self {
+        self.tag_width = tag_width;
+        self
+    }
+
+    pub fn em_width(mut self, em_width: f64) -> Self {
+        self.em_width = em_width;
+        self
+    }
+
+    pub fn scale_size_tags(mut self, scale_size_tags: bool) -> Self {
+        self.scale_size_tags = scale_size_tags;
+        self
+    }
+
+    pub fn wrap_algorithm(mut self, wrap_algorithm: WrapAlgorithmKind) -> Self {
+        self.wrap_algorithm = wrap_algorithm;
+        self
+    }
+
+    pub fn line_break(mut self, line_break: LineBreakKind) -> Self {
+        self.line_break = line_break;
+        self
+    }
+
+    pub fn fixed_columns(mut self, fixed_columns: usize) -> Self {
+        self.fixed_columns = Some(fixed_columns);
+        self
+    }
+
+    pub fn rtl(mut self, rtl: bool) -> Self {
+        self.rtl = rtl;
+        self
+    }
+
+    pub fn kinsoku(mut self, kinsoku: bool) -> Self {
+        self.kinsoku = kinsoku;
+        self
+    }
+
+    pub fn kinsoku_chars(mut self, kinsoku_chars: KinsokuChars<'a>) -> Self {
+        self.kinsoku_chars = kinsoku_chars;
+        self
+    }
+
+    #[cfg(feature = "hyphenation")]
+    pub fn hyphenate(mut self, language: Language) -> Self {
+        self.hyphenate = Some(language);
+        self
+    }
+
+    pub fn tab_width(mut self, tab_width: usize) -> Self {
+        self.tab_width = Some(tab_width);
+        self
+    }
+
+    pub fn strip_chars(mut self, strip_chars: StripChars<'a>) -> Self {
+        self.strip_chars = strip_chars;
+        self
+    }
+
+    pub fn penalties(mut self, penalties: Penalties) -> Self {
+        self.penalties = penalties;
+        self
+    }
+
+    pub fn prefer_earlier_breaks(mut self, prefer_earlier_breaks: bool) -> Self {
+        self.prefer_earlier_breaks = prefer_earlier_breaks;
+        self
+    }
+
+    /// Width of text without any tags.
+    fn text_width(&self, s: &str, case: Option<Case>) -> f64 {
+        if self.decode_entities {
+            self.measured_char_width()
+                .str_width_decoding_entities(s, case)
+        } else {
+            self.measured_char_width().str_width_in_case(s, case)
+        }
+    }
+
+    /// Width of a tag, `<space=...em>` is converted to columns and the rest use
+    /// [`Self::tag_width`].
+    fn measure_tag(&self, tag: &Segment<'_>) -> f64 {
+        if let Segment::Tag {
+            name,
+            closing: false,
+            ..
+        } = *tag
+        {
+            let length = tag.value().and_then(Length::parse);
+            if let Some(Length::Em(ems)) = length.filter(|_| name.eq_ignore_ascii_case("space")) {
+                // Negative spaces would confuse the wrap algorithms
+                return (ems * self.em_width).max(0.0);
+            }
+        }
+        if self.fixed_columns.is_some() {
+            return 0.0;
+        }
+        self.tag_width.tag_width(tag)
+    }
+
+    /// The char widths used for measuring, every char is a column with
+    /// [`Self::fixed_columns`].
+    fn measured_char_width(&self) -> CharWidth<'a> {
+        match self.fixed_columns {
+            Some(_) => CharWidth::Monospace,
+            None => self.char_width,
+        }
+    }
+
+    fn measured_wrap_algorithm(&self) -> WrapAlgorithmKind {
+        match self.fixed_columns {
+            Some(_) => WrapAlgorithmKind::FirstFit,
+            None => self.wrap_algorithm,
+        }
+    }
+
+    fn isolate_tags<'s>(&self, s: &'s str) -> IsolateTags<'s, 'a> {
+        let (open, close) = self.tag_delimiters;
+        IsolateTags::with_delimiters(s, open, close)
+            .lenient(self.lenient_tags)
+            .void_tags(self.void_tags)
+            .unclosed_tags(self.unclosed_tags)
+    }
+
+    fn parsed_tags<'s>(&self, s: &'s str) -> ParsedTags<'s, 'a> {
+        let (open, close) = self.tag_delimiters;
+        ParsedTags::with_delimiters(s, open, close)
+            .lenient(self.lenient_tags)
+            .void_tags(self.void_tags)
+            .unclosed_tags(self.unclosed_tags)
+    }
+
+    /// Checks the options that can't be wrapped to.
+    fn validate(&self) -> Result<(), WrapError> {
+        if !self.line_width_multiplier.is_finite() || self.line_width_multiplier < 0.0 {
+            return Err(WrapError::InvalidOptions(
+                "the line width multiplier must be a finite, non-negative number",
+            ));
+        }
+        if !self.em_width.is_finite() {
+            return Err(WrapError::InvalidOptions("the em width must be finite"));
+        }
+        Ok(())
+    }
+
+    /// Width of the line at `index` in the output. It isn't rounded, a multiplier like 1.15
+    /// gives fractional widths.
+    fn line_width(&self, index: usize) -> f64 {
+        let width = match self.fixed_columns {
+            Some(columns) => columns as f64,
+            None => {
+                let width = match self.line_widths.get(index).or(self.line_widths.last()) {
+                    Some(&width) => width as f32,
+                    None => self.base_line_width as f32,
+                };
+                // Multiplied as f32 so that e.g. 40 * 1.15 is exactly 46 rather than slightly
+                // less
+                f64::from(width * self.line_width_multiplier)
+            }
+        };
+        (width - self.gutter_width()).max(self.min_line_width as f64)
+    }
+
+    /// The indent of a line, `soft_break` tells whether it starts at a wrap point.
+    fn indent(&self, soft_break: bool) -> &'a str {
+        if soft_break {
+            self.subsequent_indent
+        } else {
+            self.initial_indent
+        }
+    }
+
+    fn indent_width(&self, soft_break: bool) -> f64 {
+        match self.indent(soft_break) {
+            "" => 0.0,
+            indent => measure_width_with_options(indent, self),
+        }
+    }
+
+    /// Width taken up by the line prefix and suffix.
+    fn gutter_width(&self) -> f64 {
+        if self.line_prefix.is_empty() && self.line_suffix.is_empty() {
+            return 0.0;
+        }
+        measure_width_with_options(self.line_prefix, self)
+            + measure_width_with_options(self.line_suffix, self)
+    }
+
+    /// Widths of the lines in the output starting at `first_line`.
+    fn line_widths_from(&self, first_line: usize) -> Vec<f64> {
+        if self.line_widths.len() <= 1 {
+            return vec![self.line_width(first_line)];
+        }
+        (first_line..self.line_widths.len().max(first_line + 1))
+            .map(|index| self.line_width(index))
+            .collect()
+    }
+
+    /// Width of the narrowest line, words longer than this are broken apart.
+    fn narrowest_line_width(&self) -> f64 {
+        let indent_width = self.indent_width(false).max(self.indent_width(true));
+        (0..self.line_widths.len().max(1))
+            .map(|index| (self.line_width(index) - indent_width).max(self.min_line_width as f64))
+            .fold(f64::INFINITY, f64::min)
+    }
+}
+
+/// A wrapped line before any tags are reopened or closed.
+struct Line<'a> {
+    text: Cow<'a, str>,
+    penalty: &'a str,
+    /// Visible width of the line including the penalty or ellipsis, without tags.
+    width: f64,
+    /// Byte range of the line in the input string.
+    source_range: std::ops::Range<usize>,
+    /// Whether the line starts at a wrap point rather than at the start of the input, a newline
+    /// or a `<br>`.
+    soft_break: bool,
+    /// Whether the text was cut off after this line, i.e. it should end with the ellipsis.
+    truncated: bool,
+    /// Whether the line is wider than the width it was wrapped to, see [`wrap_text_checked`].
+    overflows: bool,
+    /// Whether the line ends a page whose tags don't carry over, see
+    /// [`wrap_text_pages_with_options`].
+    ends_page: bool,
+    /// Tags that were open at the start of the line.
+    open_before: Vec<&'a str>,
+    /// Tags that are still open at the end of the line.
+    open_after: Vec<&'a str>,
+    align: Align,
+    line_height: f32,
+}
+
+impl<'a> Line<'a> {
+    fn into_cow(self, options: &WrapOptions<'_>) -> Cow<'a, str> {
+        let closing_tags = self.closing_tags(options);
+        // Reopen the tags that were left open by the previous lines
+        let mut result = Cow::Owned(self.open_before.concat());
+        result += self.text;
+        if self.truncated {
+            result.to_mut().push_str(options.ellipsis);
+        } else {
+            result += self.penalty;
+        }
+
+        for tag in closing_tags {
+            result.to_mut().push_str(&tag);
+        }
+
+        if options.rtl {
+            result = Cow::Owned(rtl::to_visual_order(&result, options));
+        }
+        let indent = options.indent(self.soft_break);
+        if !options.line_prefix.is_empty() || !indent.is_empty() || !options.line_suffix.is_empty()
+        {
+            result = Cow::Owned(format!(
+                "{}{indent}{result}{}",
+                options.line_prefix, options.line_suffix
+            ));
+        }
+        result
+    }
+
+    /// The closing tags added at the end of the line for the tags that are still open, innermost
+    /// first, if they're closed there.
+    fn closing_tags(&self, options: &WrapOptions<'_>) -> Vec<String> {
+        // Nothing comes after a truncated line or the end of a page, so its tags have to be
+        // closed, and reordering right-to-left lines only works on self-contained ones
+        if !(options.close_open_tags_per_line || self.truncated || self.ends_page || options.rtl) {
+            return Vec::new();
+        }
+        let (open, close) = options.tag_delimiters;
+        self.open_after
+            .iter()
+            .rev()
+            .map(|tag| format!("{open}/{}{close}", tag_name(tag)))
+            .collect()
+    }
+}
+
+/// Visible width of the words when placed on a single line.
+fn words_width(words: &[Word<'_>]) -> f64 {
+    let mut width = 0.0;
+    let mut whitespace_width = 0.0;
+    let mut has_words = false;
+    for word in words {
+        if word.is_tag && word.width() == 0.0 {
+            // Formatting tags take up no space, but the whitespace after them does
+            if has_words {
+                whitespace_width += word.whitespace_width();
+            }
+            continue;
+        }
+        width += whitespace_width + word.width();
+        whitespace_width = word.whitespace_width();
+        has_words = true;
+    }
+    width
+}
+
+/// Wraps the lines of a string while keeping track of the open tags across them.
+struct LineWrapper<'a> {
+    word_splitter: WordSplitter,
+    /// Sizes carry over to the following lines just like the open tags.
+    sizes: SizeStack,
+    open_tags: Vec<&'a str>,
+    lines: Vec<Line<'a>>,
+    /// Number of lines that were already taken out of `lines`, or that were only counted.
+    taken_lines: usize,
+    /// Only count the lines instead of building them.
+    count_only: bool,
+    /// The first error of the wrap algorithm, the lines it failed on were wrapped with
+    /// [`WrapAlgorithmKind::FirstFit`] instead.
+    error: Option<WrapError>,
+}
+
+impl<'a> LineWrapper<'a> {
+    fn new(options: &WrapOptions<'_>) -> Self {
+        Self {
+            word_splitter: word_splitter(options),
+            sizes: SizeStack::default(),
+            open_tags: Vec::new(),
+            lines: Vec::new(),
+            taken_lines: 0,
+            count_only: false,
+            error: None,
+        }
+    }
+
+    /// Like [`LineWrapper::new`], but the lines are only counted.
+    fn counting(options: &WrapOptions<'_>) -> Self {
+        Self {
+            count_only: true,
+            ..Self::new(options)
+        }
+    }
+
+    /// Number of lines wrapped so far.
+    fn line_count(&self) -> usize {
+        self.taken_lines + self.lines.len()
+    }
+
+    /// Takes the lines that were wrapped since the last call.
+    fn take_lines(&mut self) -> Vec<Line<'a>> {
+        self.taken_lines += self.lines.len();
+        std::mem::take(&mut self.lines)
+    }
+
+    /// Pushes a line made of `line_words`, `position` is the byte offset used if there are no
+    /// words and `soft_break` tells whether the line starts at a wrap point.
+    fn push_line(
+        &mut self,
+        line: &'a str,
+        mut line_words: &[Word<'a>],
+        position: usize,
+        soft_break: bool,
+        truncated: bool,
+        options: &WrapOptions<'_>,
+    ) {
+        if self.count_only {
+            // The open tags are still needed for the indentation of the following lines
+            for word in line_words {
+                if word.is_tag && word.width() == 0.0 && !is_hidden(word, options) {
+                    update_open_tags(&mut self.open_tags, word.word, options);
+                }
+            }
+            self.taken_lines += 1;
+            return;
+        }
+
+        if truncated {
+            // Drop trailing words until the ellipsis fits, which leaves only the ellipsis if even
+            // the first word doesn't fit along with it
+            let ellipsis_width = options.measured_char_width().str_width(options.ellipsis);
+            let line_width =
+                options.line_width(self.line_count()) - options.indent_width(soft_break);
+            while let Some(last) = line_words
+                .iter()
+                .rposition(|w| !w.is_tag || w.width() != 0.0)
+            {
+                if words_width(line_words) + ellipsis_width <= line_width {
+                    break;
+                }
+                line_words = &line_words[..last];
+            }
+            // The ellipsis goes before the closing tags at the end, they're closed after it
+            if let Some(last) = line_words
+                .iter()
+                .rposition(|w| !w.is_tag || w.width() != 0.0)
+            {
+                line_words = &line_words[..=last];
+            }
+        }
+
+        let open_before = self.open_tags.clone();
+        let (Some(first_word), Some(last_word)) = (line_words.first(), line_words.last()) else {
+            self.lines.push(Line {
+                text: Cow::from(""),
+                penalty: "",
+                width: 0.0,
+                source_range: position..position,
+                soft_break,
+                truncated,
+                overflows: false,
+                ends_page: false,
+                align: Align::from_open_tags(&open_before),
+                line_height: line_height(&open_before),
+                open_after: open_before.clone(),
+                open_before,
+            });
+            return;
+        };
+
+        // The line is usually just a slice of `line`, unless there are gaps between the words
+        // from soft hyphens or collapsed whitespace
+        let start = offset_in(line, first_word.word);
+        let word_end = offset_in(line, last_word.word) + last_word.len();
+        let mut end = word_end;
+        // The whitespace isn't right after the word if a soft hyphen was left out in between
+        let mut has_trailing_gap = false;
+        if options.preserve_spaces && !last_word.whitespace.is_empty() {
+            end = offset_in(line, last_word.whitespace) + last_word.whitespace.len();
+            has_trailing_gap = end != word_end + last_word.whitespace.len();
+        }
+
+        let mut style = None;
+        for word in line_words {
+            if style.is_none() && !(word.is_tag && word.width() == 0.0) {
+                // Tags at the very start of the line also apply to it
+                style = Some((
+                    Align::from_open_tags(&self.open_tags),
+                    line_height(&self.open_tags),
+                ));
+            }
+            // Tags with a width are glyphs rather than formatting, so they aren't reopened
+            if word.is_tag && word.width() == 0.0 && !is_hidden(word, options) {
+                update_open_tags(&mut self.open_tags, word.word, options);
+            }
+        }
+
+        // Build the text from the words if some of them have to be left out
+        let has_gaps = has_trailing_gap
+            || line_words.windows(2).any(|pair| {
+                offset_in(line, pair[0].word) + pair[0].len() + pair[0].whitespace.len()
+                    != offset_in(line, pair[1].word)
+            });
+        let text = if has_gaps
+            || line_words
+                .iter()
+                .any(|word| word.truncated || is_hidden(word, options))
+        {
+            let mut text = String::with_capacity(end - start);
+            for (i, word) in line_words.iter().enumerate() {
+                if !is_hidden(word, options) {
+                    text.push_str(word.word);
+                }
+                if word.truncated {
+                    text.push_str(options.ellipsis);
+                }
+                if i != line_words.len() - 1 || options.preserve_spaces {
+                    text.push_str(word.whitespace);
+                }
+            }
+            Cow::Owned(text)
+        } else {
+            Cow::Borrowed(&line[start..end])
+        };
+
+        let (align, line_height) = style.unwrap_or_else(|| {
+            (
+                Align::from_open_tags(&self.open_tags),
+                line_height(&self.open_tags),
+            )
+        });
+        let end_width = if truncated {
+            options.measured_char_width().str_width(options.ellipsis)
+        } else {
+            last_word.penalty_width()
+        };
+        self.lines.push(Line {
+            text,
+            penalty: last_word.penalty,
+            width: words_width(line_words) + end_width,
+            source_range: start..end,
+            soft_break,
+            truncated,
+            overflows: false,
+            ends_page: false,
+            align,
+            line_height,
+            open_before,
+            open_after: self.open_tags.clone(),
+        });
+    }
+
+    /// Pushes a line without any tags that fits as a whole as is, which is most short strings
+    /// like labels and names, without splitting it into words first. Returns whether the line
+    /// was pushed, anything that might not end up on a single line unchanged is left to
+    /// [`LineWrapper::wrap_line`].
+    fn wrap_plain_line(&mut self, line: &'a str, options: &WrapOptions<'_>) -> bool {
+        let (open, _) = options.tag_delimiters;
+        let is_plain = options.max_words_per_line.is_none()
+            && options
+                .max_lines
+                .is_none_or(|max_lines| self.line_count() + 1 < max_lines)
+            && (!options.collapse_whitespace || options.preserve_spaces)
+            && (!options.scale_size_tags || self.sizes.scale() == 1.0)
+            && self.sizes.mspace().is_none()
+            && self.sizes.case().is_none()
+            && !line.trim().is_empty()
+            && memchr::memchr(open as u8, line.as_bytes()).is_none()
+            && !(options.decode_entities && line.contains('&'))
+            && !line.contains('\u{ad}');
+        if !is_plain {
+            return false;
+        }
+
+        let end = if options.preserve_spaces {
+            line.len()
+        } else {
+            line.trim_end_matches(' ').len()
+        };
+        let width = options.measured_char_width().str_width(&line[..end]);
+        let line_width = options.line_width(self.line_count());
+        let available = (line_width
+            - indentation(&self.open_tags, line_width, options)
+            - options.indent_width(false))
+        .max(options.min_line_width as f64)
+        .min(options.narrowest_line_width());
+        // Lines that barely fit might not fit once the widths of the words are added up
+        if width > available - 1e-6 {
+            return false;
+        }
+
+        if self.count_only {
+            self.taken_lines += 1;
+            return true;
+        }
+        self.lines.push(Line {
+            text: Cow::Borrowed(&line[..end]),
+            penalty: "",
+            width,
+            source_range: 0..end,
+            soft_break: false,
+            truncated: false,
+            overflows: false,
+            ends_page: false,
+            align: Align::from_open_tags(&self.open_tags),
+            line_height: line_height(&self.open_tags),
+            open_before: self.open_tags.clone(),
+            open_after: self.open_tags.clone(),
+        });
+        true
+    }
+
+    /// Wraps a single line of the input, `rest` is the text after it (including the newline).
+    fn wrap_line(&mut self, line: &'a str, rest: &'a str, options: &WrapOptions<'_>) {
+        if self.wrap_plain_line(line, options) {
+            return;
+        }
+
+        let has_more = !rest.is_empty();
+        let line_width = options.narrowest_line_width();
+        let mut words = Vec::new();
+        let mut pieces = Vec::new();
+        for word in custom_word_separator(line, rest, options, &self.word_splitter, &mut self.sizes)
+        {
+            if word.width() > line_width && !word.is_tag && !word.nobr {
+                // The parts of compounds are only hyphenated if they don't fit
+                pieces.extend(word.hyphenate(&self.word_splitter, &options.measured_char_width()));
+            } else {
+                pieces.push(word);
+            }
+            for word in pieces.drain(..) {
+                // Tags must stay intact, only break actual text, and <nobr> overflows instead
+                if word.width() <= line_width || word.is_tag || word.nobr {
+                    words.push(word);
+                } else if options.break_long_words {
+                    let kinsoku = options.kinsoku.then_some(&options.kinsoku_chars);
+                    words.extend(word.break_apart(
+                        line_width,
+                        &options.measured_char_width(),
+                        kinsoku,
+                    ));
+                } else if options.overflow == Overflow::Truncate {
+                    words.push(word.truncate(
+                        line_width,
+                        options.ellipsis,
+                        &options.measured_char_width(),
+                    ));
+                } else {
+                    if options.overflow == Overflow::Error {
+                        self.error.get_or_insert(WrapError::WordTooWide);
+                    }
+                    words.push(word);
+                }
+            }
+        }
+        if options.preserve_spaces {
+            // Tags keep their whitespace separate so they can still be left out when fitting
+            for word in words.iter_mut().filter(|word| !word.is_tag) {
+                word.absorb_whitespace();
+            }
+        }
+
+        // Forced line breaks split the line into groups that are wrapped separately
+        let mut groups = Vec::new();
+        let mut group_start = 0;
+        let mut position = 0;
+        for (i, word) in words.iter().enumerate() {
+            if is_line_break(word) {
+                groups.push((&words[group_start..i], position));
+                group_start = i + 1;
+                position = offset_in(line, word.word) + word.len() + word.whitespace.len();
+            }
+        }
+        groups.push((&words[group_start..], position));
+
+        for (group_i, &(words, position)) in groups.iter().enumerate() {
+            let mut line_widths = Vec::new();
+            let wrapped = if words.is_empty() {
+                vec![words]
+            } else {
+                let base_widths = options.line_widths_from(self.line_count());
+                let width_of = |i: usize, open_tags: &[&str]| {
+                    let width = base_widths[i.min(base_widths.len() - 1)];
+                    let indentation =
+                        indentation(open_tags, width, options) + options.indent_width(i > 0);
+                    (width - indentation).max(options.min_line_width as f64)
+                };
+                let mut wrap = |line_widths: &[f64]| {
+                    match custom_wrap_algorithm(words, line_widths, options) {
+                        Ok(wrapped) => wrapped,
+                        Err(error) => {
+                            // First fit can't fail, but keep the group on a single line if it
+                            // does
+                            self.error.get_or_insert(error);
+                            let options =
+                                options.clone().wrap_algorithm(WrapAlgorithmKind::FirstFit);
+                            custom_wrap_algorithm(words, line_widths, &options)
+                                .unwrap_or_else(|_| vec![words])
+                        }
+                    }
+                };
+
+                // The indentation of a line is set by the tags open at its start, which depends
+                // on where the lines before it are broken, so the group is wrapped again with
+                // the widths of the lines it ended up with until they stop changing
+                let start_tags = self.open_tags.clone();
+                let mut open_tags = start_tags.clone();
+                for word in words
+                    .iter()
+                    .take_while(|word| word.is_tag && word.width() == 0.0)
+                {
+                    update_open_tags(&mut open_tags, word.word, options);
+                }
+                // The first line can have a different indent than the rest, so there are two widths
+                let line_count = base_widths.len().max(2);
+                line_widths = (0..line_count).map(|i| width_of(i, &open_tags)).collect();
+                let mut wrapped = wrap(&line_widths);
+                for _ in 0..MAX_INDENT_PASSES {
+                    let mut open_tags = start_tags.clone();
+                    let mut widths = Vec::with_capacity(wrapped.len() + 1);
+                    for (i, line_words) in wrapped.iter().enumerate() {
+                        let leading = line_words
+                            .iter()
+                            .take_while(|word| word.is_tag && word.width() == 0.0)
+                            .count();
+                        for word in &line_words[..leading] {
+                            update_open_tags(&mut open_tags, word.word, options);
+                        }
+                        widths.push(width_of(i, &open_tags));
+                        for word in line_words[leading..].iter().filter(|word| word.is_tag) {
+                            update_open_tags(&mut open_tags, word.word, options);
+                        }
+                    }
+                    // Lines that are added by wrapping again start after all of the tags
+                    widths.extend(
+                        (wrapped.len()..line_count.max(wrapped.len() + 1))
+                            .map(|i| width_of(i, &open_tags)),
+                    );
+                    let settled = widths
+                        .iter()
+                        .enumerate()
+                        .all(|(i, width)| line_widths[i.min(line_widths.len() - 1)] == *width);
+                    if settled {
+                        break;
+                    }
+                    line_widths = widths;
+                    wrapped = wrap(&line_widths);
+                }
+                wrapped
+            };
+
+            for (i, line_words) in wrapped.iter().enumerate() {
+                let is_last = i == wrapped.len() - 1 && group_i == groups.len() - 1 && !has_more;
+                let truncated = match options.max_lines {
+                    Some(max_lines) if self.line_count() >= max_lines => return,
+                    Some(max_lines) => self.line_count() + 1 == max_lines && !is_last,
+                    None => false,
+                };
+                let line_count = self.lines.len();
+                self.push_line(line, line_words, position, i > 0, truncated, options);
+                if let (Some(pushed), Some(&width)) = (
+                    self.lines.get_mut(line_count),
+                    line_widths.get(i).or(line_widths.last()),
+                ) {
+                    // Allow for the rounding errors of adding up fractional widths
+                    pushed.overflows = pushed.width > width + 1e-9;
+                }
+            }
+        }
+    }
+}
+
+fn wrap_lines<'a>(string: &'a str, options: &WrapOptions<'_>) -> Vec<Line<'a>> {
+    let mut wrapper = LineWrapper::new(options);
+    wrap_lines_into(&mut wrapper, string, options);
+    wrapper.lines
+}
+
+/// Like [`wrap_lines`], but fails instead of falling back to [`WrapAlgorithmKind::FirstFit`].
+fn try_wrap_lines<'a>(
+    string: &'a str,
+    options: &WrapOptions<'_>,
+) -> Result<Vec<Line<'a>>, WrapError> {
+    options.validate()?;
+    let mut wrapper = LineWrapper::new(options);
+    wrap_lines_into(&mut wrapper, string, options);
+    match wrapper.error {
+        Some(error) => Err(error),
+        None => Ok(wrapper.lines),
+    }
+}
+
+fn wrap_lines_into<'a>(wrapper: &mut LineWrapper<'a>, string: &'a str, options: &WrapOptions<'_>) {
+    if string.trim().is_empty() {
+        return;
+    }
+
+    for line in string.split('\n') {
+        if options
+            .max_lines
+            .is_some_and(|max_lines| wrapper.line_count() >= max_lines)
+        {
+            break;
+        }
+
+        let first_line = wrapper.lines.len();
+        let offset = offset_in(string, line);
+        wrapper.wrap_line(line, &string[offset + line.len()..], options);
+
+        // Make the ranges relative to the whole string
+        for line in &mut wrapper.lines[first_line..] {
+            line.source_range = line.source_range.start + offset..line.source_range.end + offset;
+        }
+    }
+}
+
+/// Wraps the string into lines, reopening any formatting tags that are still open at the
+/// start of each continuation line.
+///
+/// Empty and whitespace-only strings produce no lines at all. A string made of only tags is
+/// a single line with all the tags, and tags are never split even if they're wider than the
+/// line. The lines the optimal fit algorithm fails on are wrapped with first fit instead, see
+/// [`try_wrap_text`] to get an error.
+///
+/// Formatting tags are left out when choosing the breaks, so a run of them like
+/// `</b></color><i>` is never split up by the wrap algorithm: the closing tags end the line
+/// and the opening tags start the next one along with the word after them.
+pub fn wrap_text(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+) -> Vec<Cow<'_, str>> {
+    wrap_text_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+    )
+}
+
+pub fn wrap_text_with_options<'a>(string: &'a str, options: &WrapOptions<'_>) -> Vec<Cow<'a, str>> {
+    let Ok((lines, _)) = wrap_normalized(string, options, |string| {
+        Ok::<_, Infallible>(wrap_lines(string, options))
+    });
+    lines
+}
+
+/// Normalizes the string and wraps it with `wrap`, the lines only borrow from `string` if
+/// nothing had to be normalized. Also returns whether any of the lines overflows.
+fn wrap_normalized<'a, E>(
+    string: &'a str,
+    options: &WrapOptions<'_>,
+    wrap: impl for<'s> FnOnce(&'s str) -> Result<Vec<Line<'s>>, E>,
+) -> Result<(Vec<Cow<'a, str>>, bool), E> {
+    let overflows = |lines: &[Line<'_>]| lines.iter().any(|line| line.overflows);
+    Ok(
+        match normalize::apply(string, &normalize::replacements(string, options)) {
+            Cow::Borrowed(string) => {
+                let lines = wrap(string)?;
+                let overflows = overflows(&lines);
+                let lines = lines.into_iter().map(|line| line.into_cow(options));
+                (lines.collect(), overflows)
+            }
+            Cow::Owned(string) => {
+                let lines = wrap(&string)?;
+                let overflows = overflows(&lines);
+                let lines = lines
+                    .into_iter()
+                    .map(|line| Cow::Owned(line.into_cow(options).into_owned()));
+                (lines.collect(), overflows)
+            }
+        },
+    )
+}
+
+/// Like [`wrap_text`], but returns an error instead of falling back to
+/// [`WrapAlgorithmKind::FirstFit`] when the optimal fit algorithm fails, or when the options are
+/// invalid.
+pub fn try_wrap_text(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+) -> Result<Vec<Cow<'_, str>>, WrapError> {
+    try_wrap_text_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+    )
+}
+
+pub fn try_wrap_text_with_options<'a>(
+    string: &'a str,
+    options: &WrapOptions<'_>,
+) -> Result<Vec<Cow<'a, str>>, WrapError> {
+    wrap_normalized(string, options, |string| try_wrap_lines(string, options))
+        .map(|(lines, _)| lines)
+}
+
+/// Like [`wrap_text`], but also tells whether any of the lines is wider than the line width,
+/// e.g. because of a word that couldn't be broken apart. The widths are the ones the lines
+/// were wrapped to, so the indentation and line prefixes are taken into account.
+pub fn wrap_text_checked(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+) -> (Vec<Cow<'_, str>>, bool) {
+    wrap_text_checked_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+    )
+}
+
+pub fn wrap_text_checked_with_options<'a>(
+    string: &'a str,
+    options: &WrapOptions<'_>,
+) -> (Vec<Cow<'a, str>>, bool) {
+    let Ok(checked) = wrap_normalized(string, options, |string| {
+        Ok::<_, Infallible>(wrap_lines(string, options))
+    });
+    checked
+}
+
+/// Like [`wrap_text`], but joins the lines with `\n` into a single string.
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = wrapTextJoined))]
+pub fn wrap_text_joined(string: &str, base_line_width: i32, line_width_multiplier: f32) -> String {
+    wrap_text_joined_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+    )
+}
+
+pub fn wrap_text_joined_with_options(string: &str, options: &WrapOptions<'_>) -> String {
+    let string = normalize::apply(string, &normalize::replacements(string, options));
+    let mut joined = String::with_capacity(string.len());
+    for (i, line) in wrap_lines(&string, options).into_iter().enumerate() {
+        if i > 0 {
+            joined.push('\n');
+        }
+        joined += &line.into_cow(options);
+    }
+    joined
+}
+
+/// Iterator over the wrapped lines of a string, see [`wrap_text_iter`].
+pub struct WrapTextIter<'a, 'o> {
+    string: &'a str,
+    options: Cow<'o, WrapOptions<'o>>,
+    wrapper: LineWrapper<'a>,
+    /// The paragraphs that haven't been wrapped yet, `None` once there's nothing left to wrap.
+    paragraphs: Option<std::str::Split<'a, char>>,
+    lines: std::vec::IntoIter<Cow<'a, str>>,
+}
+
+impl<'a, 'o> WrapTextIter<'a, 'o> {
+    fn new(string: &'a str, options: Cow<'o, WrapOptions<'o>>) -> Self {
+        let wrapper = LineWrapper::new(&options);
+        if !normalize::replacements(string, &options).is_empty() {
+            // The normalized string can't be borrowed from, so it's wrapped all at once instead
+            let lines = wrap_text_with_options(string, &options)
+                .into_iter()
+                .map(|line| Cow::Owned(line.into_owned()))
+                .collect::<Vec<_>>();
+            return Self {
+                string,
+                options,
+                wrapper,
+                paragraphs: None,
+                lines: lines.into_iter(),
+            };
+        }
+
+        Self {
+            string,
+            options,
+            wrapper,
+            // Like wrap_lines, blank strings have no lines
+            paragraphs: (!string.trim().is_empty()).then(|| string.split('\n')),
+            lines: Vec::new().into_iter(),
+        }
+    }
+}
+
+impl<'a> Iterator for WrapTextIter<'a, '_> {
+    type Item = Cow<'a, str>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        loop {
+            if let Some(line) = self.lines.next() {
+                return Some(line);
+            }
+
+            let paragraphs = self.paragraphs.as_mut()?;
+            let options = &*self.options;
+            let Some(line) = paragraphs.next().filter(|_| {
+                options
+                    .max_lines
+                    .is_none_or(|max_lines| self.wrapper.line_count() < max_lines)
+            }) else {
+                self.paragraphs = None;
+                return None;
+            };
+
+            let rest = &self.string[offset_in(self.string, line) + line.len()..];
+            self.wrapper.wrap_line(line, rest, options);
+            self.lines = self
+                .wrapper
+                .take_lines()
+                .into_iter()
+                .map(|line| line.into_cow(options))
+                .collect::<Vec<_>>()
+                .into_iter();
+        }
+    }
+}
+
+/// Like [`wrap_text`], but wraps the string one paragraph at a time as the lines are consumed.
+///
+/// Only the lines of the current paragraph (the text up to the next `\n`) are kept in memory
+/// rather than all of them, which keeps the peak memory usage low for very large scripts. The
+/// optimal fit algorithm still needs a whole paragraph at once, so a single huge paragraph uses
+/// as much memory as with [`wrap_text`]. Strings with tabs to expand or characters to strip
+/// are wrapped all at once.
+pub fn wrap_text_iter(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+) -> WrapTextIter<'_, 'static> {
+    WrapTextIter::new(
+        string,
+        Cow::Owned(WrapOptions::new(base_line_width).multiplier(line_width_multiplier)),
+    )
+}
+
+pub fn wrap_text_iter_with_options<'a, 'o>(
+    string: &'a str,
+    options: &'o WrapOptions<'o>,
+) -> WrapTextIter<'a, 'o> {
+    WrapTextIter::new(string, Cow::Borrowed(options))
+}
+
+/// Horizontal alignment of a line, set by the `<align>` tag that is active at the start of it.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq)]
+pub enum Align {
+    #[default]
+    Left,
+    Center,
+    Right,
+    Justified,
+    Flush,
+}
+
+impl Align {
+    /// Alignment of the innermost `<align>` tag of the open tags, unknown values are left
+    /// aligned like Unity does.
+    fn from_open_tags(open_tags: &[&str]) -> Self {
+        let Some(value) = open_tags
+            .iter()
+            .rev()
+            .find(|tag| tag_name(tag).eq_ignore_ascii_case("align"))
+            .and_then(|tag| segment::parse_tag(tag).value())
+        else {
+            return Align::Left;
+        };
+
+        [
+            ("center", Align::Center),
+            ("right", Align::Right),
+            ("justified", Align::Justified),
+            ("flush", Align::Flush),
+        ]
+        .into_iter()
+        .find(|(name, _)| value.eq_ignore_ascii_case(name))
+        .map_or(Align::Left, |(_, align)| align)
+    }
+}
+
+/// Line height multiplier of the innermost `<line-height>` tag of the open tags, 1 if there is
+/// none. Percentages and ems are relative to the normal line height, other units like pixels
+/// can't be converted and count as 1.
+fn line_height(open_tags: &[&str]) -> f32 {
+    open_tags
+        .iter()
+        .rev()
+        .find(|tag| tag_name(tag).eq_ignore_ascii_case("line-height"))
+        .and_then(|tag| segment::parse_tag(tag).value())
+        .and_then(Length::parse)
+        .map_or(1.0, |length| match length {
+            Length::Percent(percent) => (percent / 100.0) as f32,
+            Length::Em(ems) => ems as f32,
+        })
+}
+
+/// A wrapped line along with the formatting tags that are active around it.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct WrappedLine {
+    /// The line text, without any reopened or closed tags.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone))]
+    pub text: String,
+    /// Tags that were open at the start of the line, outermost first. [`wrap_text`] reopens
+    /// all of them at the start of the line, with tags that aren't part of the input.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone, js_name = openBefore))]
+    pub open_before: Vec<String>,
+    /// Tags that are still open at the end of the line, outermost first.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone, js_name = openAfter))]
+    pub open_after: Vec<String>,
+    /// Closing tags that [`wrap_text`] adds at the end of the line for the [`Self::open_after`]
+    /// tags, innermost first, e.g. `</b>`. Empty if it leaves them open. Along with the
+    /// reopened [`Self::open_before`] tags, those are the ones to remove to get back to the
+    /// input, the closing tags in [`Self::text`] are all part of it.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone, js_name = closingTags))]
+    pub closing_tags: Vec<String>,
+    /// Alignment of the line, from the `<align>` tag active at the start of it.
+    pub align: Align,
+    /// Line height multiplier from the `<line-height>` tag active at the start of the line, e.g.
+    /// 1.2 for `<line-height=120%>`. 1 if there is none.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name = lineHeight))]
+    pub line_height: f32,
+    /// Visible width of the line in columns, rounded up. Tags don't count unless they have a
+    /// width, the hyphen or ellipsis at the end does.
+    pub width: usize,
+    /// Byte range of the line in the input string, without the reopened and closed tags that
+    /// aren't part of the input.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(skip))]
+    pub source_range: std::ops::Range<usize>,
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+impl WrappedLine {
+    /// Byte offset of the start of the line in the input string.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter, js_name = sourceStart))]
+    pub fn source_start(&self) -> usize {
+        self.source_range.start
+    }
+
+    /// Byte offset of the end of the line in the input string.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter, js_name = sourceEnd))]
+    pub fn source_end(&self) -> usize {
+        self.source_range.end
+    }
+}
+
+/// Like [`wrap_text`], but returns the tag state of each line instead of reopening tags, so the
+/// caller can apply the styling themselves.
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = wrapTextSpans))]
+pub fn wrap_text_spans(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+) -> Vec<WrappedLine> {
+    wrap_text_spans_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+    )
+}
+
+pub fn wrap_text_spans_with_options(string: &str, options: &WrapOptions<'_>) -> Vec<WrappedLine> {
+    let to_owned = |tags: &[&str]| tags.iter().map(|tag| tag.to_string()).collect();
+    let replacements = normalize::replacements(string, options);
+    let normalized = normalize::apply(string, &replacements);
+    let mut offsets = normalize::OriginalOffsets::new(&replacements);
+    wrap_lines(&normalized, options)
+        .into_iter()
+        .map(|line| WrappedLine {
+            closing_tags: line.closing_tags(options),
+            source_range: offsets.map(line.source_range.start)..offsets.map(line.source_range.end),
+            text: if line.truncated {
+                line.text.into_owned() + options.ellipsis
+            } else {
+                line.text.into_owned() + line.penalty
+            },
+            open_before: to_owned(&line.open_before),
+            open_after: to_owned(&line.open_after),
+            align: line.align,
+            line_height: line.line_height,
+            width: line.width.ceil() as usize,
+        })
+        .collect()
+}
+
+/// Returns the byte offsets in `string` where line breaks should be inserted, so the caller can
+/// wrap the text without allocating new strings.
+///
+/// Each offset is the start of a wrapped line, so it always lies on a char boundary and never
+/// splits a tag. Existing newlines and `<br>` tags are not reported since they already break
+/// the line. Hyphens added by the splitter and reopened tags are not represented.
+pub fn wrap_breaks(string: &str, base_line_width: i32, line_width_multiplier: f32) -> Vec<usize> {
+    wrap_breaks_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+    )
+}
+
+pub fn wrap_breaks_with_options(string: &str, options: &WrapOptions<'_>) -> Vec<usize> {
+    let replacements = normalize::replacements(string, options);
+    let normalized = normalize::apply(string, &replacements);
+
+    // Map the offsets back to the original string, lines never start inside an expanded tab
+    let mut offsets = normalize::OriginalOffsets::new(&replacements);
+    wrap_lines(&normalized, options)
+        .into_iter()
+        .filter(|line| line.soft_break)
+        .map(|line| offsets.map(line.source_range.start))
+        .collect()
+}
+
+/// The lines of every string wrapped by [`wrap_text_batch`], flattened into a single list.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct WrappedBatch {
+    /// The lines of all the strings, in order.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone))]
+    pub lines: Vec<String>,
+    /// Number of lines each string was wrapped into, used to split `lines` back up.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone, js_name = lineCounts))]
+    pub line_counts: Vec<u32>,
+}
+
+/// Wraps many strings at once, like calling [`wrap_text`] on each of them.
+///
+/// Every call across the wasm boundary has to marshal its arguments and results, so wrapping a
+/// whole dialogue dump in one call avoids most of that overhead. The lines are returned as one
+/// flat list rather than nested arrays for the same reason.
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = wrapTextBatch))]
+pub fn wrap_text_batch(
+    strings: Vec<String>,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+) -> WrappedBatch {
+    let options = WrapOptions::new(base_line_width).multiplier(line_width_multiplier);
+    let mut batch = WrappedBatch {
+        lines: Vec::new(),
+        line_counts: Vec::with_capacity(strings.len()),
+    };
+    for string in &strings {
+        let lines = wrap_text_with_options(string, &options);
+        batch.line_counts.push(lines.len() as u32);
+        batch
+            .lines
+            .extend(lines.into_iter().map(|line| line.into_owned()));
+    }
+    batch
+}
+
+/// Like [`wrap_text_batch`], but calls `f` with the index of the string and each of its lines as
+/// they're wrapped instead of collecting them, see [`wrap_text_iter`]. Stops at the first error
+/// `f` returns.
+pub fn wrap_text_batch_each<S: AsRef<str>, E>(
+    strings: &[S],
+    options: &WrapOptions<'_>,
+    mut f: impl FnMut(usize, &str) -> Result<(), E>,
+) -> Result<(), E> {
+    for (index, string) in strings.iter().enumerate() {
+        for line in wrap_text_iter_with_options(string.as_ref(), options) {
+            f(index, &line)?;
+        }
+    }
+    Ok(())
+}
+
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+#[wasm_bindgen]
+extern "C" {
+    /// A JS function that is called with each wrapped line and the index of its string.
+    #[wasm_bindgen(typescript_type = "(line: string, index: number) => void")]
+    pub type LineCallback;
+
+    #[wasm_bindgen(method, catch, js_name = call)]
+    fn call(
+        this: &LineCallback,
+        this_arg: &JsValue,
+        line: &str,
+        index: usize,
+    ) -> Result<JsValue, JsValue>;
+}
+
+/// Like [`wrap_text_batch`], but passes each line to `callback` as soon as it's wrapped, so
+/// the lines of a whole script never have to be held in memory or marshalled at once.
+///
+/// Every call still crosses the wasm boundary, which costs more than marshalling one more
+/// string in a batch, so [`wrap_text_batch`] is faster unless the result is too big to build.
+/// If the callback throws, wrapping stops and the exception is rethrown to the caller, the
+/// lines before it have already been passed on.
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+#[wasm_bindgen(js_name = wrapTextBatchStreaming)]
+pub fn wrap_text_batch_streaming(
+    strings: Vec<String>,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+    callback: &LineCallback,
+) -> Result<(), JsValue> {
+    let options = WrapOptions::new(base_line_width).multiplier(line_width_multiplier);
+    wrap_text_batch_each(&strings, &options, |index, line| {
+        callback.call(&JsValue::UNDEFINED, line, index).map(drop)
+    })
+}
+
+/// Returns the number of lines the string is wrapped into, like `wrap_text(..).len()` but
+/// without building the lines.
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = wrapLineCount))]
+pub fn wrap_line_count(string: &str, base_line_width: i32, line_width_multiplier: f32) -> usize {
+    wrap_line_count_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+    )
+}
+
+pub fn wrap_line_count_with_options(string: &str, options: &WrapOptions<'_>) -> usize {
+    let string = normalize::apply(string, &normalize::replacements(string, options));
+    let mut wrapper = LineWrapper::counting(options);
+    wrap_lines_into(&mut wrapper, &string, options);
+    wrapper.line_count()
+}
+
+/// Returns whether the string fits on `max_lines` lines, without building the lines.
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = fitsInLines))]
+pub fn fits_in_lines(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+    max_lines: usize,
+) -> bool {
+    fits_in_lines_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+        max_lines,
+    )
+}
+
+pub fn fits_in_lines_with_options(
+    string: &str,
+    options: &WrapOptions<'_>,
+    max_lines: usize,
+) -> bool {
+    // Wrapping stops as soon as there's one line too many
+    let options = options.clone().max_lines(max_lines.saturating_add(1));
+    wrap_line_count_with_options(string, &options) <= max_lines
+}
+
+/// Returns the total height of the wrapped text, where every line is `line_height` high times
+/// the `<line-height>` that is active at the start of it, see [`WrappedLine::line_height`].
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = wrappedHeight))]
+pub fn wrapped_height(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+    line_height: f32,
+) -> f32 {
+    wrapped_height_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+        line_height,
+    )
+}
+
+pub fn wrapped_height_with_options(
+    string: &str,
+    options: &WrapOptions<'_>,
+    line_height: f32,
+) -> f32 {
+    let string = normalize::apply(string, &normalize::replacements(string, options));
+    wrap_lines(&string, options)
+        .iter()
+        .fold(0.0, |height, line| height + line.line_height * line_height)
+}
+
+/// Returns the display width of the string in columns, ignoring any tags.
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = measureWidth))]
+pub fn measure_width(s: &str) -> usize {
+    measure_width_with_options(s, &WrapOptions::new(0)).ceil() as usize
+}
+
+/// Returns the number of user-perceived characters (grapheme clusters) in the string, ignoring
+/// any tags. An emoji or a letter with combining marks counts as a single character.
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = visibleLen))]
+pub fn visible_len(s: &str) -> usize {
+    ParsedTags::new(s)
+        .map(|segment| match segment {
+            Segment::Text(text) => text.graphemes(true).count(),
+            Segment::Tag { .. } => 0,
+        })
+        .sum()
+}
+
+/// Returns the display width of the string like [`measure_width`], along with the number of
+/// bytes taken up by the tags in it. The whitespace after a tag is text, so it isn't counted
+/// as tag bytes. Together with `s.len()` this tells how much of a byte budget goes to markup.
+pub fn budget_len(s: &str) -> (usize, usize) {
+    let tag_bytes = ParsedTags::new(s)
+        .map(|segment| match segment {
+            Segment::Tag { raw, .. } => raw.len(),
+            Segment::Text(_) => 0,
+        })
+        .sum();
+    (measure_width(s), tag_bytes)
+}
+
+/// Returns the width of the string as measured by the wrapper, i.e. using the char widths and
+/// tag widths of the options.
+pub fn measure_width_with_options(s: &str, options: &WrapOptions<'_>) -> f64 {
+    let mut sizes = SizeStack::default();
+    options
+        .parsed_tags(s)
+        .map(|segment| {
+            sizes.update(&segment);
+            let scale = if options.scale_size_tags {
+                sizes.scale()
+            } else {
+                1.0
+            };
+            match segment {
+                Segment::Text(text) => match sizes.mspace() {
+                    Some(ems) => {
+                        text.graphemes(true).count() as f64 * ems * options.em_width * scale
+                    }
+                    None => options.text_width(text, sizes.case()) * scale,
+                },
+                Segment::Tag { .. } => options.measure_tag(&segment) * scale,
+            }
+        })
+        .sum()
+}
+
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = rewrap))]
+pub fn rewrap_owned(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+    all_soft: bool,
+) -> Vec<String> {
+    let options = WrapOptions::new(base_line_width).multiplier(line_width_multiplier);
+    rewrap_with_options(string, &options, all_soft)
+        .into_iter()
+        .map(|s| s.into_owned())
+        .collect()
 }
 
-#[wasm_bindgen(js_name = wrapText)]
 pub fn wrap_text_owned(
     string: &str,
     base_line_width: i32,
@@ -251,3 +3193,752 @@ pub fn wrap_text_owned(
         .map(|s| s.into_owned())
         .collect()
 }
+
+/// Like [`wrap_text_owned`], but returns an error like [`try_wrap_text`] does.
+pub fn try_wrap_text_owned(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+) -> Result<Vec<String>, WrapError> {
+    Ok(
+        try_wrap_text(string, base_line_width, line_width_multiplier)?
+            .into_iter()
+            .map(|s| s.into_owned())
+            .collect(),
+    )
+}
+
+/// `wrapText` in JS, where the errors of [`try_wrap_text_owned`] are thrown as exceptions.
+#[cfg(all(feature = "wasm", target_arch = "wasm32"))]
+#[wasm_bindgen(js_name = wrapText)]
+pub fn wrap_text_js(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+) -> Result<Vec<String>, JsError> {
+    Ok(try_wrap_text_owned(
+        string,
+        base_line_width,
+        line_width_multiplier,
+    )?)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn text_that_looks_like_a_tag_is_measured() {
+        // Neither of these is a tag, so they take up their full width
+        assert_eq!(wrap_text("<x> yy zz", 6, 1.0), vec!["<x> yy", "zz"]);
+        assert_eq!(wrap_text("i <3> you", 5, 1.0), vec!["i <3>", "you"]);
+    }
+
+    #[test]
+    fn trailing_tags_stay_on_the_last_line() {
+        assert_eq!(
+            wrap_text("a <color=red><b>the end</b></color>", 3, 1.0),
+            vec!["a", "<color=red><b>the", "<color=red><b>end</b></color>"]
+        );
+        assert_eq!(
+            wrap_text("<color=red><b>end</b></color>", 3, 1.0),
+            vec!["<color=red><b>end</b></color>"]
+        );
+        assert_eq!(wrap_line_count("a b<b></b></b></color>", 1, 1.0), 2);
+    }
+
+    #[test]
+    fn runs_of_tags_are_never_split() {
+        let text = "some <b><color=red>long bold text</color></b> <i>and then italics</i> after";
+        for algorithm in [WrapAlgorithmKind::OptimalFit, WrapAlgorithmKind::FirstFit] {
+            for width in 1..=30 {
+                let options = WrapOptions::new(width).wrap_algorithm(algorithm);
+                for line in wrap_text_spans_with_options(text, &options) {
+                    assert_ne!(
+                        line.width, 0,
+                        "{:?} at {width} with {algorithm:?}",
+                        line.text
+                    );
+                }
+            }
+        }
+        assert_eq!(
+            wrap_text("<b><color=red>some long</color></b> <i>text</i>", 9, 1.0),
+            vec!["<b><color=red>some long</color></b>", "<i>text</i>"]
+        );
+    }
+
+    #[test]
+    fn line_break_kinds() {
+        let text = "see /usr/local/share/doc";
+        let options = |line_break| WrapOptions::new(12).line_break(line_break);
+        assert_eq!(
+            wrap_text(text, 12, 1.0),
+            vec!["see /usr/", "local/share/", "doc"]
+        );
+        assert_eq!(
+            wrap_text_with_options(text, &options(LineBreakKind::Uax14)),
+            vec!["see /usr/", "local/share/", "doc"]
+        );
+        assert_eq!(
+            wrap_text_with_options(text, &options(LineBreakKind::Words)),
+            vec!["see", "/usr/local/s", "hare/doc"]
+        );
+    }
+
+    #[test]
+    fn tags_cut_off_at_the_end_are_text() {
+        let chunks: Vec<_> = IsolateTags::new("hello <colo").collect();
+        assert_eq!(chunks, vec![("hello ", false), ("<colo", false)]);
+        // Even for tags that don't need to be closed
+        let chunks: Vec<_> = IsolateTags::new("<b>hello</b> <size=40").collect();
+        assert_eq!(
+            chunks,
+            vec![
+                ("<b>", true),
+                ("hello", false),
+                ("</b> ", true),
+                ("<size=40", false),
+            ]
+        );
+        let chunks: Vec<_> = IsolateTags::new("a<br").collect();
+        assert_eq!(chunks, vec![("a", false), ("<br", false)]);
+        let chunks: Vec<_> = IsolateTags::new("<b>x</b").collect();
+        assert_eq!(chunks, vec![("<b>x", false), ("</b", false)]);
+    }
+
+    #[test]
+    fn invalid_multiplier_is_an_error() {
+        assert_eq!(
+            try_wrap_text_owned("aaa bbb", 8, f32::NAN),
+            Err(WrapError::InvalidOptions(
+                "the line width multiplier must be a finite, non-negative number"
+            ))
+        );
+        // The infallible version falls back instead of panicking
+        assert!(!wrap_text_owned("aaa bbb", 8, f32::NAN).is_empty());
+    }
+
+    #[test]
+    fn whitespace_after_tags_is_measured() {
+        assert_eq!(
+            wrap_text_checked("<b>aaaa</b> bbbb", 8, 1.0),
+            (vec![Cow::from("<b>aaaa</b>"), Cow::from("bbbb")], false)
+        );
+        assert_eq!(
+            wrap_text_checked("a <b>b</b> c d e", 2, 1.0),
+            (
+                vec![
+                    Cow::from("a"),
+                    Cow::from("<b>b</b>"),
+                    Cow::from("c"),
+                    Cow::from("d"),
+                    Cow::from("e")
+                ],
+                false
+            )
+        );
+    }
+
+    #[test]
+    fn overflow_includes_whitespace_after_tags() {
+        // The glued words can't be broken up, the space after `</b>` makes them 9 wide
+        let (lines, overflows) = wrap_text_checked("<nobr><b>aaaa</b> bbbb</nobr>", 8, 1.0);
+        assert_eq!(lines, vec!["<nobr><b>aaaa</b> bbbb</nobr>"]);
+        assert!(overflows);
+        assert!(!wrap_text_checked("<nobr><b>aaaa</b> bbbb</nobr>", 9, 1.0).1);
+    }
+
+    #[test]
+    fn span_widths_include_whitespace_after_tags() {
+        let widths = |width| {
+            wrap_text_spans("<b>aaaa</b> bbbb", width, 1.0)
+                .into_iter()
+                .map(|line| (line.text, line.width))
+                .collect::<Vec<_>>()
+        };
+        assert_eq!(widths(9), vec![("<b>aaaa</b> bbbb".to_string(), 9)]);
+        assert_eq!(
+            widths(8),
+            vec![("<b>aaaa</b>".to_string(), 4), ("bbbb".to_string(), 4)]
+        );
+    }
+
+    #[test]
+    fn tags_between_short_words() {
+        assert_eq!(
+            wrap_text("a <b>b</b> c d e", 1, 1.0),
+            vec!["a", "<b>b</b>", "c", "d", "e"]
+        );
+        assert_eq!(
+            wrap_text("a <b>b</b> c d e", 3, 1.0),
+            vec!["a <b>b</b>", "c d", "e"]
+        );
+        assert_eq!(
+            wrap_text("a <b>b</b> c d e", 5, 1.0),
+            vec!["a <b>b</b> c", "d e"]
+        );
+    }
+
+    #[test]
+    fn void_tags_can_be_borrowed() {
+        let names = ["icon".to_string()];
+        let names: Vec<&str> = names.iter().map(String::as_str).collect();
+        let chunks: Vec<_> = IsolateTags::new("a<icon>b").void_tags(&names).collect();
+        assert_eq!(chunks, vec![("a", false), ("<icon>", true), ("b", false)]);
+        assert_eq!(
+            wrap_text_with_options("a <icon> b", &WrapOptions::new(5).void_tags(&names)),
+            vec!["a <icon> b"]
+        );
+    }
+
+    #[test]
+    fn unclosed_size_is_a_tag() {
+        let chunks: Vec<_> = IsolateTags::new("<size=16>big").collect();
+        assert_eq!(chunks, vec![("<size=16>", true), ("big", false)]);
+        let chunks: Vec<_> = IsolateTags::new("<b>big").collect();
+        assert_eq!(chunks, vec![("<b>big", false)]);
+    }
+
+    #[test]
+    fn unclosed_align_applies_to_every_line() {
+        let lines = wrap_text_spans("<align=center>a b", 1, 1.0);
+        let aligns: Vec<_> = lines.iter().map(|line| line.align).collect();
+        assert_eq!(aligns, vec![Align::Center, Align::Center]);
+        assert_eq!(lines[1].text, "b");
+    }
+
+    #[test]
+    fn unclosed_line_height_applies_until_the_end() {
+        assert_eq!(
+            wrapped_height("<line-height=200%>a b c", 1, 1.0, 10.0),
+            60.0
+        );
+    }
+
+    #[test]
+    fn unclosed_indent_and_margin_apply_until_the_end() {
+        assert_eq!(
+            wrap_text("<indent=2>aa bb cc", 4, 1.0),
+            vec!["<indent=2>aa", "<indent=2>bb", "<indent=2>cc"]
+        );
+        assert_eq!(
+            wrap_text("<margin-left=2>aa bb", 4, 1.0),
+            vec!["<margin-left=2>aa", "<margin-left=2>bb"]
+        );
+    }
+
+    #[test]
+    fn multi_byte_whitespace_after_tags() {
+        let chunks: Vec<_> =
+            IsolateTags::new("<b>\u{3042} </b>\u{3000}\u{5168}\u{89d2}\u{7a7a}\u{767d}").collect();
+        assert_eq!(
+            chunks,
+            vec![
+                ("<b>", true),
+                ("\u{3042} ", false),
+                ("</b>\u{3000}", true),
+                ("\u{5168}\u{89d2}\u{7a7a}\u{767d}", false),
+            ]
+        );
+        assert_eq!(
+            wrap_text(
+                "<b>\u{3042} </b>\u{3000}\u{5168}\u{89d2}\u{7a7a}\u{767d}",
+                4,
+                1.0
+            ),
+            vec!["<b>\u{3042} </b>", "\u{5168}\u{89d2}", "\u{7a7a}\u{767d}"]
+        );
+    }
+
+    #[test]
+    fn closing_tags_match_case_insensitively() {
+        let chunks: Vec<_> = IsolateTags::new("<B>bold</b> <i>x</I>").collect();
+        assert_eq!(
+            chunks,
+            vec![
+                ("<B>", true),
+                ("bold", false),
+                ("</b> ", true),
+                ("<i>", true),
+                ("x", false),
+                ("</I>", true),
+            ]
+        );
+        let chunks: Vec<_> = IsolateTags::new("<noparse><b></NoParse>").collect();
+        assert_eq!(
+            chunks,
+            vec![("<noparse>", true), ("<b>", false), ("</NoParse>", true)]
+        );
+    }
+
+    #[test]
+    fn quoted_attributes_can_contain_brackets() {
+        let chunks: Vec<_> = IsolateTags::new("<link=\"a>b\">text</link>").collect();
+        assert_eq!(
+            chunks,
+            vec![("<link=\"a>b\">", true), ("text", false), ("</link>", true)]
+        );
+        let chunks: Vec<_> = IsolateTags::new("<link='a<b>'>text</link>").collect();
+        assert_eq!(
+            chunks,
+            vec![("<link='a<b>'>", true), ("text", false), ("</link>", true)]
+        );
+    }
+
+    #[test]
+    fn blank_paragraphs_are_kept() {
+        assert_eq!(
+            wrap_text("line1\n\nline3", 10, 1.0),
+            vec!["line1", "", "line3"]
+        );
+        assert_eq!(
+            wrap_text("<b>line1\n\nline3</b>", 10, 1.0),
+            vec!["<b>line1", "<b>", "<b>line3</b>"]
+        );
+    }
+
+    #[test]
+    fn empty_and_tag_only_input() {
+        assert_eq!(wrap_text("", 10, 1.0), Vec::<String>::new());
+        assert_eq!(wrap_text("   ", 10, 1.0), Vec::<String>::new());
+        assert_eq!(wrap_text("<b></b>", 10, 1.0), vec!["<b></b>"]);
+        assert_eq!(
+            wrap_text("<sprite=averyveryverylongname>", 3, 1.0),
+            vec!["<sprite=averyveryverylongname>"]
+        );
+    }
+
+    #[test]
+    fn tags_at_a_break_stay_with_their_text() {
+        // Opening tags move to the next line, closing tags stay on the line they close
+        assert_eq!(
+            wrap_text("word <b>wrap here</b>", 5, 1.0),
+            vec!["word", "<b>wrap", "<b>here</b>"]
+        );
+        assert_eq!(
+            wrap_text("<b>word</b> wrap here", 5, 1.0),
+            vec!["<b>word</b>", "wrap", "here"]
+        );
+    }
+
+    #[test]
+    fn nested_tags_with_the_same_name() {
+        let chunks: Vec<_> = IsolateTags::new("<size=1><size=2>x</size></size>").collect();
+        assert_eq!(
+            chunks,
+            vec![
+                ("<size=1>", true),
+                ("<size=2>", true),
+                ("x", false),
+                ("</size>", true),
+                ("</size>", true),
+            ]
+        );
+        // Only the inner tag is closed, so the outer one is text
+        let chunks: Vec<_> = IsolateTags::new("<b><b>x</b>").collect();
+        assert_eq!(
+            chunks,
+            vec![("<b>", false), ("<b>", true), ("x", false), ("</b>", true)]
+        );
+    }
+
+    #[test]
+    fn zero_widths_are_clamped() {
+        assert_eq!(wrap_text("ab cd", 0, 1.0), vec!["a", "b", "c", "d"]);
+        assert_eq!(wrap_text("ab cd", 10, 0.01), vec!["a", "b", "c", "d"]);
+        assert_eq!(
+            wrap_text_with_options("ab cd", &WrapOptions::new(0).min_line_width(2)),
+            vec!["ab", "cd"]
+        );
+    }
+
+    #[test]
+    fn color_and_alpha_values_are_tags() {
+        let chunks: Vec<_> = IsolateTags::new("<alpha=#80>a <color=#FFAADD>b</color>").collect();
+        assert_eq!(
+            chunks,
+            vec![
+                ("<alpha=#80>", true),
+                ("a ", false),
+                ("<color=#FFAADD>", true),
+                ("b", false),
+                ("</color>", true),
+            ]
+        );
+        assert_eq!(
+            wrap_text("<alpha=#80>a b</alpha> <color=#FFF>c</color>", 3, 1.0),
+            vec!["<alpha=#80>a b</alpha>", "<color=#FFF>c</color>"]
+        );
+    }
+
+    #[cfg(feature = "hyphenation")]
+    #[test]
+    fn existing_hyphens_are_preferred() {
+        let options = WrapOptions::new(8).hyphenate(Language::EnglishUS);
+        assert_eq!(
+            wrap_text_with_options("hyphenation", &options),
+            vec!["hyphena-", "tion"]
+        );
+        assert_eq!(
+            wrap_text_with_options("hyphen-ation", &options),
+            vec!["hyphen-", "ation"]
+        );
+        assert_eq!(
+            wrap_text_with_options("a well-known", &options),
+            vec!["a well-", "known"]
+        );
+    }
+
+    #[cfg(feature = "hyphenation")]
+    #[test]
+    fn only_embedded_languages_are_hyphenated() {
+        let options = WrapOptions::new(8).hyphenate(Language::German1996);
+        let lines = wrap_text_with_options("Donaudampfschiff", &options);
+        if cfg!(feature = "hyphenation-all") {
+            assert_eq!(lines, vec!["Donau-", "dampf-", "schiff"]);
+        } else {
+            assert_eq!(lines, vec!["Donaudam", "pfschiff"]);
+        }
+    }
+
+    #[test]
+    fn nobr_is_never_broken() {
+        assert_eq!(
+            wrap_text("x <nobr>abcdefghij klm</nobr> y", 5, 1.0),
+            vec!["x", "<nobr>abcdefghij klm</nobr>", "y"]
+        );
+        // An unterminated <nobr> runs to the end of the string
+        assert_eq!(
+            wrap_text("Hello <nobr>Player One", 2, 1.0),
+            vec!["He", "ll", "o", "<nobr>Player One"]
+        );
+        // Nested ones end with the outermost
+        assert_eq!(
+            wrap_text("a <nobr><nobr>bb</nobr> cc</nobr> dd", 2, 1.0),
+            vec!["a", "<nobr><nobr>bb</nobr> cc</nobr>", "dd"]
+        );
+    }
+
+    #[test]
+    fn indentation_follows_the_tags_open_at_every_line() {
+        // Closing the tag gives the following lines the full width back
+        assert_eq!(
+            wrap_text("<indent=50%>aa</indent> bb cc dd ee ff gg", 10, 1.0),
+            vec!["<indent=50%>aa</indent> bb", "cc dd ee", "ff gg"]
+        );
+        // Opening it mid-paragraph indents the lines after it
+        assert_eq!(
+            wrap_text("aa bb <indent=50%>cc dd ee ff gg", 10, 1.0),
+            vec![
+                "aa bb <indent=50%>cc",
+                "<indent=50%>dd ee",
+                "<indent=50%>ff gg"
+            ]
+        );
+        assert_eq!(
+            wrap_text(
+                "aa bb <margin=2em>cc dd ee ff</margin> gg hh ii jj",
+                10,
+                1.0
+            ),
+            vec![
+                "aa bb <margin=2em>cc",
+                "<margin=2em>dd ee",
+                "<margin=2em>ff</margin> gg",
+                "hh ii jj"
+            ]
+        );
+    }
+
+    #[test]
+    fn spans_tell_the_added_closing_tags_apart() {
+        let options = WrapOptions::new(5).close_open_tags_per_line(true);
+        let text = "<b>aa <i>bb</i> cc</b>";
+        let spans = wrap_text_spans_with_options(text, &options);
+        // The </i> is part of the input, the </b> is added to close the line
+        assert_eq!(spans[0].text, "<b>aa <i>bb</i>");
+        assert_eq!(spans[0].closing_tags, vec!["</b>"]);
+        assert!(spans[1].closing_tags.is_empty());
+        let lines: Vec<_> = spans
+            .iter()
+            .map(|line| line.open_before.concat() + &line.text + &line.closing_tags.concat())
+            .collect();
+        assert_eq!(lines, wrap_text_with_options(text, &options));
+    }
+
+    #[test]
+    fn break_offsets() {
+        assert_eq!(wrap_breaks("aaa bbb <b>ccc</b> ddd", 7, 1.0), vec![8]);
+        let text = "héllo wörld ünïcode";
+        let breaks = wrap_breaks(text, 7, 1.0);
+        assert_eq!(breaks, vec![7, 14]);
+        assert!(breaks.iter().all(|&offset| text.is_char_boundary(offset)));
+        // Newlines already break the line
+        assert_eq!(wrap_breaks("aa\nbb cc dd", 7, 1.0), vec![9]);
+        assert!(wrap_breaks("<color=red>aaa bbb</color>", 7, 1.0).is_empty());
+    }
+
+    #[test]
+    fn open_tags_are_reopened_on_the_next_line() {
+        let text = "<color=#ff0000>this is a long red sentence that wraps</color>";
+        let lines = vec![
+            "<color=#ff0000>this is a long red",
+            "<color=#ff0000>sentence that wraps</color>",
+        ];
+        assert_eq!(wrap_text(text, 20, 1.0), lines);
+        assert_eq!(wrap_text_owned(text, 20, 1.0), lines);
+        // Tags closed on their own line and void tags aren't carried over
+        assert_eq!(
+            wrap_text("<b>aa</b> bb <i>cc dd</i> <sprite=1> ee", 5, 1.0),
+            vec!["<b>aa</b> bb", "<i>cc dd</i>", "<sprite=1> ee"]
+        );
+    }
+
+    #[test]
+    fn open_tags_are_closed_per_line() {
+        let options = WrapOptions::new(12).close_open_tags_per_line(true);
+        assert_eq!(
+            wrap_text_with_options("<b>bold text spanning multiple lines</b>", &options),
+            vec![
+                "<b>bold text</b>",
+                "<b>spanning</b>",
+                "<b>multiple</b>",
+                "<b>lines</b>"
+            ]
+        );
+        // </color> closes the innermost <color=...> whatever its value
+        let options = WrapOptions::new(7).close_open_tags_per_line(true);
+        assert_eq!(
+            wrap_text_with_options(
+                "<color=red><color=#00f>aaa bbb</color> ccc ddd</color>",
+                &options
+            ),
+            vec![
+                "<color=red><color=#00f>aaa bbb</color></color>",
+                "<color=red>ccc ddd</color>"
+            ]
+        );
+    }
+
+    #[test]
+    fn br_tags_break_the_line() {
+        assert_eq!(
+            wrap_text("first line<br>second<br/>third one here", 40, 1.0),
+            vec!["first line", "second", "third one here"]
+        );
+        assert_eq!(
+            wrap_text("aa bb<br>cc dd ee", 5, 1.0),
+            vec!["aa bb", "cc dd", "ee"]
+        );
+    }
+
+    #[test]
+    fn max_lines_end_with_an_ellipsis() {
+        let options = WrapOptions::new(10).max_lines(2);
+        let text = "one two three four five six seven";
+        // "three four" would fit, but not along with the ellipsis
+        assert_eq!(
+            wrap_text_with_options(text, &options),
+            vec!["one two", "three…"]
+        );
+        assert_eq!(
+            wrap_text_with_options(text, &options.clone().ellipsis("...")),
+            vec!["one two", "three..."]
+        );
+        // The tags of the truncated line are still closed
+        assert_eq!(
+            wrap_text_with_options(&format!("<b>{text}</b>"), &options),
+            vec!["<b>one two", "<b>three…</b>"]
+        );
+    }
+
+    #[test]
+    fn lenient_tags_allow_whitespace_before_the_name() {
+        let text = "< b >bold</ b> text";
+        // Strict by default
+        let chunks: Vec<_> = IsolateTags::new(text).collect();
+        assert_eq!(chunks, vec![("< b >bold", false), ("</ b> text", false)]);
+        let chunks: Vec<_> = IsolateTags::new(text).lenient(true).collect();
+        assert_eq!(
+            chunks,
+            vec![
+                ("< b >", true),
+                ("bold", false),
+                ("</ b> ", true),
+                ("text", false)
+            ]
+        );
+        assert_eq!(
+            wrap_text_with_options("< b >aaa bbb</ b>", &WrapOptions::new(3).lenient_tags(true)),
+            vec!["< b >aaa", "< b >bbb</ b>"]
+        );
+    }
+
+    #[test]
+    fn strip_tags_keeps_only_the_text() {
+        assert_eq!(
+            strip_tags("<color=red>red</color> and <b>bold</b>"),
+            "red and bold"
+        );
+        assert!(matches!(strip_tags("no tags"), Cow::Borrowed("no tags")));
+    }
+
+    #[test]
+    fn visible_len_counts_grapheme_clusters() {
+        assert_eq!(visible_len("<b>héllo</b>"), 5);
+        // The family emoji and the combining accent are one character each
+        assert_eq!(
+            visible_len("\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467} e\u{301}"),
+            3
+        );
+        assert_eq!(visible_len("<size=20>ab</size><sprite=1>"), 2);
+    }
+
+    #[test]
+    fn custom_tag_delimiters() {
+        let chunks: Vec<_> = IsolateTags::with_delimiters("[b]bold[/b] text", '[', ']').collect();
+        assert_eq!(
+            chunks,
+            vec![
+                ("[b]", true),
+                ("bold", false),
+                ("[/b] ", true),
+                ("text", false)
+            ]
+        );
+        let options = WrapOptions::new(3).tag_delimiters('[', ']');
+        assert_eq!(
+            wrap_text_with_options("[b]aaa bbb[/b] ccc", &options),
+            vec!["[b]aaa", "[b]bbb[/b]", "ccc"]
+        );
+        // The added closing tags use the delimiters too
+        let options = options.close_open_tags_per_line(true);
+        assert_eq!(
+            wrap_text_with_options("[b]aaa bbb[/b] ccc", &options),
+            vec!["[b]aaa[/b]", "[b]bbb[/b]", "ccc"]
+        );
+    }
+
+    #[test]
+    fn soft_hyphens_are_break_opportunities() {
+        // A hyphen is shown where the line is broken, and nothing where it isn't
+        assert_eq!(
+            wrap_text("extra\u{ad}ordinary", 8, 1.0),
+            vec!["extra-", "ordinary"]
+        );
+        assert_eq!(
+            wrap_text("extra\u{ad}ordinary", 20, 1.0),
+            vec!["extraordinary"]
+        );
+    }
+
+    #[test]
+    fn entities_are_measured_decoded() {
+        let options = WrapOptions::new(5).decode_entities(true);
+        assert_eq!(measure_width_with_options("&lt;b&gt;", &options), 3.0);
+        // The output keeps the entities, and they never start a tag
+        assert_eq!(
+            wrap_text_with_options("a &lt;b&gt; c &amp; d", &options),
+            vec!["a &lt;b&gt;", "c &amp; d"]
+        );
+        assert_eq!(
+            wrap_text_with_options("a &lt;b&gt; c", &WrapOptions::new(5)),
+            vec!["a", "&lt;b", "&gt;", "c"]
+        );
+    }
+
+    #[test]
+    fn space_tags_take_up_their_width() {
+        let options = WrapOptions::new(8).em_width(2.0);
+        assert_eq!(measure_width_with_options("aa<space=2em>bb", &options), 8.0);
+        assert_eq!(
+            wrap_text_with_options("aa<space=2em>bb cc", &options),
+            vec!["aa<space=2em>bb", "cc"]
+        );
+        assert_eq!(
+            wrap_text("aa<space=5em>bb cc", 10, 1.0),
+            vec!["aa<space=5em>", "bb cc"]
+        );
+    }
+
+    #[test]
+    fn line_prefixes_and_suffixes() {
+        // The gutters take up width, and tags are reopened after the prefix
+        let options = WrapOptions::new(9).line_prefix("> ");
+        assert_eq!(
+            wrap_text_with_options("<b>aaa bbb ccc</b>", &options),
+            vec!["> <b>aaa bbb", "> <b>ccc</b>"]
+        );
+        let options = options.line_suffix(" |");
+        assert_eq!(
+            wrap_text_with_options("aaa bbb ccc", &options),
+            vec!["> aaa |", "> bbb |", "> ccc |"]
+        );
+    }
+
+    #[test]
+    fn non_breaking_spaces_join_words() {
+        assert_eq!(
+            wrap_text("aaa\u{a0}bbb ccc", 8, 1.0),
+            vec!["aaa\u{a0}bbb", "ccc"]
+        );
+        let options = WrapOptions::new(6).nbsp_is_breakable(true);
+        assert_eq!(
+            wrap_text_with_options("aaa\u{a0}bbb ccc", &options),
+            vec!["aaa", "bbb", "ccc"]
+        );
+    }
+
+    #[test]
+    fn budget_len_counts_tag_bytes() {
+        assert_eq!(budget_len("<color=red>red</color> <b>text</b>"), (8, 26));
+        // The visible length is the width, not the bytes
+        assert_eq!(budget_len("あい"), (4, 0));
+    }
+
+    #[test]
+    fn case_tags_change_the_measured_width() {
+        let advances = [('a', 1.0), ('A', 2.0), ('b', 1.0), ('B', 2.0)];
+        let metrics = FontMetrics::new(advances.into_iter().collect(), 1.0);
+        let options = WrapOptions::with_font_metrics(&metrics, 6.0);
+        assert_eq!(
+            measure_width_with_options("<uppercase>ab</uppercase>", &options),
+            4.0
+        );
+        assert_eq!(
+            measure_width_with_options("<lowercase>AB</lowercase>", &options),
+            2.0
+        );
+        assert_eq!(
+            measure_width_with_options("<smallcaps>ab</smallcaps>", &options),
+            3.2
+        );
+        // The text keeps its case
+        assert_eq!(
+            wrap_text_with_options("aa aa bb", &options),
+            vec!["aa aa", "bb"]
+        );
+        assert_eq!(
+            wrap_text_with_options("aa <uppercase>aa</uppercase> bb", &options),
+            vec!["aa", "<uppercase>aa</uppercase>", "bb"]
+        );
+    }
+
+    #[test]
+    fn truncation_makes_room_for_the_ellipsis() {
+        let options = WrapOptions::new(6).max_lines(1).ellipsis("...");
+        // Both "cc" and "bb" have to go for the ellipsis to fit after "aa"
+        assert_eq!(wrap_text_with_options("aa bb cc", &options), vec!["aa..."]);
+        // Only the ellipsis is left when no word fits along with it
+        assert_eq!(wrap_text_with_options("aaaaa bb cc", &options), vec!["..."]);
+        let options = WrapOptions::new(8).max_lines(1).break_long_words(false);
+        assert_eq!(wrap_text_with_options("abcdefgh ij", &options), vec!["…"]);
+        // Tags still open are closed after the ellipsis
+        let options = WrapOptions::new(6).max_lines(1);
+        assert_eq!(
+            wrap_text_with_options("<b>aa bb</b> <i>cc dd</i>", &options),
+            vec!["<b>aa bb…</b>"]
+        );
+    }
+}
@@ -0,0 +1,142 @@
+use std::borrow::Cow;
+
+use crate::{offset_in, Segment, WrapOptions};
+
+/// Characters that are removed from the input before wrapping.
+#[derive(Debug, Clone, Copy, Default)]
+pub enum StripChars<'a> {
+    /// Keep every character.
+    None,
+    /// Only the byte order mark U+FEFF, zero width spaces (U+200B) are kept since they are
+    /// legitimate break opportunities.
+    #[default]
+    Bom,
+    /// The byte order mark and all control characters except tabs and newlines, i.e.
+    /// U+0000-U+0008, U+000B-U+001F (including `\r`) and U+007F-U+009F.
+    Controls,
+    /// Exactly these characters.
+    Custom(&'a [char]),
+}
+
+impl StripChars<'_> {
+    pub fn contains(&self, c: char) -> bool {
+        match self {
+            StripChars::None => false,
+            StripChars::Bom => c == '\u{feff}',
+            StripChars::Controls => {
+                c == '\u{feff}' || (c.is_control() && !matches!(c, '\t' | '\n'))
+            }
+            StripChars::Custom(chars) => chars.contains(&c),
+        }
+    }
+}
+
+/// A character of the input that is replaced before wrapping.
+pub(crate) struct Replacement {
+    /// Byte offset of the character in the input.
+    pub offset: usize,
+    /// Byte length of the character.
+    pub len: usize,
+    /// Number of spaces it's replaced with, zero if it's removed.
+    pub spaces: usize,
+}
+
+/// Returns the characters that have to be stripped, and the tabs that have to be expanded to
+/// the next tab stop. Tags don't count towards the column.
+pub(crate) fn replacements(string: &str, options: &WrapOptions<'_>) -> Vec<Replacement> {
+    let tab_width = options
+        .tab_width
+        .filter(|&tab_width| tab_width > 0 && string.contains('\t'));
+    if tab_width.is_none() && !string.contains(|c| options.strip_chars.contains(c)) {
+        return Vec::new();
+    }
+
+    let mut replacements = Vec::new();
+    let mut column = 0.0;
+    for segment in options.parsed_tags(string) {
+        let (text, is_tag) = match segment {
+            Segment::Text(text) => (text, false),
+            Segment::Tag { raw, .. } => (raw, true),
+        };
+        let offset = offset_in(string, text);
+        for (i, c) in text.char_indices() {
+            if options.strip_chars.contains(c) {
+                replacements.push(Replacement {
+                    offset: offset + i,
+                    len: c.len_utf8(),
+                    spaces: 0,
+                });
+                continue;
+            }
+            if is_tag {
+                continue;
+            }
+
+            match (c, tab_width) {
+                ('\t', Some(tab_width)) => {
+                    let spaces = tab_width - column as usize % tab_width;
+                    replacements.push(Replacement {
+                        offset: offset + i,
+                        len: 1,
+                        spaces,
+                    });
+                    column += spaces as f64;
+                }
+                ('\n', _) => column = 0.0,
+                _ => column += options.measured_char_width().char_width(c),
+            }
+        }
+    }
+    replacements
+}
+
+/// Applies the replacements to the string.
+pub(crate) fn apply<'a>(string: &'a str, replacements: &[Replacement]) -> Cow<'a, str> {
+    if replacements.is_empty() {
+        return Cow::Borrowed(string);
+    }
+
+    let mut result = String::with_capacity(string.len());
+    let mut last = 0;
+    for replacement in replacements {
+        result.push_str(&string[last..replacement.offset]);
+        result.extend(std::iter::repeat_n(' ', replacement.spaces));
+        last = replacement.offset + replacement.len;
+    }
+    result.push_str(&string[last..]);
+    Cow::Owned(result)
+}
+
+/// Maps offsets in the string with the replacements applied back to the original string.
+pub(crate) struct OriginalOffsets<'r> {
+    replacements: std::iter::Peekable<std::slice::Iter<'r, Replacement>>,
+    /// Difference between the offsets in the two strings so far.
+    shift: isize,
+}
+
+impl<'r> OriginalOffsets<'r> {
+    pub fn new(replacements: &'r [Replacement]) -> Self {
+        Self {
+            replacements: replacements.iter().peekable(),
+            shift: 0,
+        }
+    }
+
+    /// Returns the original offset, the offsets have to be passed in increasing order. An
+    /// offset inside of an expanded tab is mapped to the end of the tab.
+    pub fn map(&mut self, offset: usize) -> usize {
+        let offset = offset as isize;
+        while let Some(replacement) = self.replacements.peek() {
+            let start = replacement.offset as isize + self.shift;
+            if start >= offset {
+                break;
+            }
+            if offset < start + replacement.spaces as isize {
+                return replacement.offset + replacement.len;
+            }
+            self.shift += replacement.spaces as isize - replacement.len as isize;
+            self.replacements.next();
+        }
+        (offset - self.shift) as usize
+    }
+}
@@ -0,0 +1,97 @@
+use textwrap::{core::Fragment, wrap_algorithms::Penalties};
+
+/// Moves every line break of the optimal fit lines as far back as it can go without making
+/// the lines any worse, see [`crate::WrapOptions::prefer_earlier_breaks`].
+///
+/// The breaks are moved one fragment at a time starting with the first one, and a move is
+/// only taken if the cost of the two lines around the break doesn't go up. Lines are never
+/// emptied, so the number of lines stays the same.
+pub(crate) fn prefer_earlier_breaks<'a, T: Fragment>(
+    fragments: &'a [T],
+    lines: Vec<&'a [T]>,
+    line_widths: &[f64],
+    penalties: &Penalties,
+) -> Vec<&'a [T]> {
+    if lines.len() < 2 {
+        return lines;
+    }
+
+    let mut starts: Vec<usize> = lines
+        .iter()
+        .scan(0, |start, line| {
+            let line_start = *start;
+            *start += line.len();
+            Some(line_start)
+        })
+        .collect();
+    starts.push(fragments.len());
+
+    let cost = |line: usize, start: usize, end: usize| {
+        line_cost(
+            fragments,
+            start,
+            end,
+            line_width(line_widths, line),
+            penalties,
+        )
+    };
+    for i in 1..starts.len() - 1 {
+        while starts[i] - starts[i - 1] > 1 {
+            let (before, at, after) = (starts[i - 1], starts[i], starts[i + 1]);
+            let current = cost(i - 1, before, at) + cost(i, at, after);
+            let moved = cost(i - 1, before, at - 1) + cost(i, at - 1, after);
+            // The costs are sums of fractional widths, so equal ones can differ a tiny bit
+            if moved > current + 1e-9 * current.abs().max(1.0) {
+                break;
+            }
+            starts[i] -= 1;
+        }
+    }
+
+    starts
+        .windows(2)
+        .map(|pair| &fragments[pair[0]..pair[1]])
+        .collect()
+}
+
+/// Width of the line at `index`, the last width is used for all the lines after it.
+fn line_width(line_widths: &[f64], index: usize) -> f64 {
+    line_widths
+        .get(index)
+        .or(line_widths.last())
+        .copied()
+        .unwrap_or(0.0)
+}
+
+/// Cost of a line made of `fragments[start..end]`, computed the same way as
+/// [`textwrap::wrap_algorithms::wrap_optimal_fit`] does.
+fn line_cost<T: Fragment>(
+    fragments: &[T],
+    start: usize,
+    end: usize,
+    line_width: f64,
+    penalties: &Penalties,
+) -> f64 {
+    let target_width = line_width.max(1.0);
+    let line = &fragments[start..end];
+    let width = line
+        .iter()
+        .map(|fragment| fragment.width() + fragment.whitespace_width())
+        .sum::<f64>()
+        - line[line.len() - 1].whitespace_width()
+        + line[line.len() - 1].penalty_width();
+
+    let mut cost = penalties.nline_penalty as f64;
+    if width > target_width {
+        cost += (width - target_width) * penalties.overflow_penalty as f64;
+    } else if end < fragments.len() {
+        let gap = target_width - width;
+        cost += gap * gap;
+    } else if start + 1 == end && width < target_width / penalties.short_last_line_fraction as f64 {
+        cost += penalties.short_last_line_penalty as f64;
+    }
+    if line[line.len() - 1].penalty_width() > 0.0 {
+        cost += penalties.hyphen_penalty as f64;
+    }
+    cost
+}
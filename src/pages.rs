@@ -0,0 +1,113 @@
+use crate::{normalize, offset_in, LineWrapper, Segment, WrapOptions};
+
+/// Splits the text into pages at its `<page>` tags and wraps every page on its own, like a
+/// dialogue box that shows one page at a time. The `<page>` tags are left out of the output,
+/// and so is a newline right before or after one since the page break already ends the line.
+///
+/// Every page starts without any open tags and the tags that are still open at the end of a
+/// page are closed there, see [`wrap_text_pages_with_options`] for carrying them over to the
+/// next page instead. A page without any text has no lines, like a blank string.
+pub fn wrap_text_pages(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+) -> Vec<Vec<String>> {
+    wrap_text_pages_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+        false,
+    )
+}
+
+/// Like [`wrap_text_pages`], `carry_tags` keeps the tags that are still open at the end of a
+/// page open on the next one, so they're reopened at its start and can be closed on a later
+/// page. Options like [`WrapOptions::max_lines`] apply to every page separately.
+pub fn wrap_text_pages_with_options(
+    string: &str,
+    options: &WrapOptions<'_>,
+    carry_tags: bool,
+) -> Vec<Vec<String>> {
+    let text = normalize::apply(string, &normalize::replacements(string, options));
+    let mut pages = Vec::new();
+    let mut wrapper = LineWrapper::new(options);
+    for page in split_pages(&text, options) {
+        if carry_tags {
+            // Only the tags carry over, the lines are counted per page
+            wrapper.taken_lines = 0;
+        } else {
+            wrapper = LineWrapper::new(options);
+        }
+        if !page.trim().is_empty() {
+            for line in page.split('\n') {
+                if options
+                    .max_lines
+                    .is_some_and(|max_lines| wrapper.line_count() >= max_lines)
+                {
+                    break;
+                }
+                let end = offset_in(&text, line) + line.len();
+                wrapper.wrap_line(line, &text[end..], options);
+            }
+        }
+        let mut lines = wrapper.take_lines();
+        if !carry_tags {
+            // Tags that don't carry over end with the page, even if they're closed on a later one
+            if let Some(line) = lines.last_mut() {
+                line.ends_page = true;
+            }
+        }
+        pages.push(
+            lines
+                .into_iter()
+                .map(|line| line.into_cow(options).into_owned())
+                .collect(),
+        );
+    }
+    pages
+}
+
+/// The text of every page, without the `<page>` tags and the newlines around them.
+fn split_pages<'a>(text: &'a str, options: &WrapOptions<'_>) -> Vec<&'a str> {
+    let mut pages = Vec::new();
+    let mut start = 0;
+    for segment in options.parsed_tags(text) {
+        let Segment::Tag {
+            raw,
+            name,
+            closing: false,
+            ..
+        } = segment
+        else {
+            continue;
+        };
+        if name.eq_ignore_ascii_case("page") {
+            let end = offset_in(text, raw);
+            let page = &text[start..end];
+            pages.push(page.strip_suffix('\n').unwrap_or(page));
+            start = end + raw.len();
+            if text[start..].starts_with('\n') {
+                start += 1;
+            }
+        }
+    }
+    pages.push(&text[start..]);
+    pages
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tags_spanning_pages() {
+        let text = "aa bb<page>cc <b>dd<page>ee</b>";
+        assert_eq!(
+            wrap_text_pages(text, 2, 1.0),
+            vec![vec!["aa", "bb"], vec!["cc", "<b>dd</b>"], vec!["ee</b>"]]
+        );
+        assert_eq!(
+            wrap_text_pages_with_options(text, &WrapOptions::new(2), true),
+            vec![vec!["aa", "bb"], vec!["cc", "<b>dd"], vec!["<b>ee</b>"]]
+        );
+    }
+}
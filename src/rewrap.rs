@@ -0,0 +1,169 @@
+use std::borrow::Cow;
+
+use crate::{
+    measure_width_with_options, segment::parse_tag, update_open_tags, wrap_text_with_options,
+    CharWidth, Segment, WrapOptions,
+};
+
+/// Wraps text that was already wrapped to a different width, e.g. after the text box was
+/// resized. Newlines that look like earlier wrap points are joined back into paragraphs first,
+/// blank lines always stay.
+///
+/// A single newline is only taken as a wrap point if the first word after it wouldn't have fit
+/// on the line before it, judging by the widest line of the text. This guesses wrong when
+/// the lines were balanced by [`crate::WrapAlgorithmKind::OptimalFit`] and broken early, see
+/// [`rewrap_with_options`] for treating every single newline as a wrap point instead.
+pub fn rewrap(string: &str, base_line_width: i32, line_width_multiplier: f32) -> Vec<Cow<'_, str>> {
+    rewrap_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+        false,
+    )
+}
+
+/// Like [`rewrap`], `all_soft` joins every single newline and only keeps the blank lines.
+pub fn rewrap_with_options<'a>(
+    string: &'a str,
+    options: &WrapOptions<'_>,
+    all_soft: bool,
+) -> Vec<Cow<'a, str>> {
+    match unwrap_soft_breaks(string, options, all_soft) {
+        Cow::Borrowed(string) => wrap_text_with_options(string, options),
+        Cow::Owned(string) => wrap_text_with_options(&string, options)
+            .into_iter()
+            .map(|line| Cow::Owned(line.into_owned()))
+            .collect(),
+    }
+}
+
+/// Joins the lines that were split at a wrap point. Tags that were reopened at the start of a
+/// line, like [`crate::wrap_text`] does, are removed again.
+fn unwrap_soft_breaks<'a>(
+    string: &'a str,
+    options: &WrapOptions<'_>,
+    all_soft: bool,
+) -> Cow<'a, str> {
+    let has_gutters = [
+        options.line_prefix,
+        options.line_suffix,
+        options.initial_indent,
+        options.subsequent_indent,
+    ]
+    .iter()
+    .any(|gutter| !gutter.is_empty());
+    if !string.contains('\n') && !has_gutters {
+        return Cow::Borrowed(string);
+    }
+
+    // The prefix, suffix and indents are added again when wrapping, the longer indent is
+    // stripped first in case one starts with the other
+    let mut indents = [options.initial_indent, options.subsequent_indent];
+    indents.sort_by_key(|indent| std::cmp::Reverse(indent.len()));
+    let lines: Vec<&str> = string
+        .split('\n')
+        .map(|line| {
+            let line = line.strip_prefix(options.line_prefix).unwrap_or(line);
+            let line = line.strip_suffix(options.line_suffix).unwrap_or(line);
+            indents
+                .iter()
+                .find_map(|indent| line.strip_prefix(indent))
+                .unwrap_or(line)
+        })
+        .collect();
+    let widths: Vec<f64> = lines
+        .iter()
+        .map(|line| measure_width_with_options(line, options))
+        .collect();
+    let is_blank = |i: usize| lines[i].trim().is_empty();
+
+    // The widest line is the closest guess of the width the text was wrapped to
+    let wrapped_width = widths.iter().copied().fold(0.0, f64::max);
+
+    let space_width = options.measured_char_width().char_width(' ');
+    let mut result = String::with_capacity(string.len());
+    let mut open_tags: Vec<&str> = Vec::new();
+    for (i, &line) in lines.iter().enumerate() {
+        let reopened = open_tags.concat();
+        let text = match line.strip_prefix(reopened.as_str()) {
+            Some(rest) if !reopened.is_empty() => rest,
+            _ => line,
+        };
+
+        if i > 0 {
+            let first_word = text.trim_start().split(' ').next().unwrap_or_default();
+            let soft = !is_blank(i - 1)
+                && !is_blank(i)
+                && (all_soft
+                    || widths[i - 1]
+                        + space_width
+                        + measure_width_with_options(first_word, options)
+                        > wrapped_width);
+            if !soft {
+                result.push('\n');
+            } else if needs_space(&result, text) {
+                result.push(' ');
+            }
+        }
+        result.push_str(text);
+
+        // The reopened tags were removed, so they don't change what's open
+        for tag in tags_in(text, options) {
+            update_open_tags(&mut open_tags, tag, options);
+        }
+    }
+    Cow::Owned(result)
+}
+
+/// The tags in a line of wrapped text. The tags that are reopened on every line leave the
+/// opening tags unbalanced, so unlike [`crate::IsolateTags`] they don't need a closing tag.
+fn tags_in<'a>(line: &'a str, options: &WrapOptions<'_>) -> impl Iterator<Item = &'a str> {
+    let (open, close) = options.tag_delimiters;
+    let mut rest = line;
+    std::iter::from_fn(move || {
+        while let Some(start) = rest.find(open) {
+            let end = rest[start..].find(close)? + start + close.len_utf8();
+            let raw = &rest[start..end];
+            rest = &rest[end..];
+            if matches!(parse_tag(raw), Segment::Tag { name, .. } if !name.is_empty()) {
+                return Some(raw);
+            }
+        }
+        None
+    })
+}
+
+/// Whether joining two lines needs a space in between, lines of wide characters like CJK text
+/// are joined directly.
+fn needs_space(before: &str, after: &str) -> bool {
+    let (Some(last), Some(first)) = (before.chars().last(), after.chars().next()) else {
+        return false;
+    };
+    let is_wide = |c| CharWidth::Unicode.char_width(c) >= 2.0;
+    let both_wide = is_wide(last) && is_wide(first);
+    !(last.is_whitespace() || first.is_whitespace() || both_wide)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn soft_breaks_are_joined_and_paragraphs_kept() {
+        let text = "aaa bbb\nccc ddd\n\neee fff";
+        assert_eq!(
+            rewrap(text, 20, 1.0),
+            vec!["aaa bbb ccc ddd", "", "eee fff"]
+        );
+        assert_eq!(
+            rewrap(text, 7, 1.0),
+            vec!["aaa bbb", "ccc ddd", "", "eee fff"]
+        );
+        // "eee" would have fit after "d", so that newline is kept unless every one is soft
+        let text = "aaa bbb ccc\nd\neee";
+        assert_eq!(rewrap(text, 20, 1.0), vec!["aaa bbb ccc d", "eee"]);
+        assert_eq!(
+            rewrap_with_options(text, &WrapOptions::new(20), true),
+            vec!["aaa bbb ccc d eee"]
+        );
+    }
+}
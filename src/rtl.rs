@@ -0,0 +1,138 @@
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{is_void_tag, Segment, WrapOptions};
+
+/// Reorders a wrapped line of right-to-left text into visual order, for renderers that draw
+/// everything left to right. The line has to be self-contained, i.e. every tag in it is closed.
+///
+/// This isn't the full bidi algorithm: the whole line is reversed, except for runs of
+/// left-to-right text like numbers and latin words, and brackets outside of those runs are
+/// mirrored.
+pub(crate) fn to_visual_order(line: &str, options: &WrapOptions<'_>) -> String {
+    let segments: Vec<Segment<'_>> = options.parsed_tags(line).collect();
+
+    // Reversing swaps the opening and closing tags, so each tag needs the one it's paired with
+    let mut pairs = vec![None; segments.len()];
+    let mut open: Vec<(usize, &str)> = Vec::new();
+    for (i, segment) in segments.iter().enumerate() {
+        let Segment::Tag {
+            name,
+            closing,
+            self_closing,
+            ..
+        } = *segment
+        else {
+            continue;
+        };
+        if closing {
+            if let Some(pos) = open.iter().rposition(|(_, n)| n.eq_ignore_ascii_case(name)) {
+                let (opening, _) = open.remove(pos);
+                pairs[opening] = Some(i);
+                pairs[i] = Some(opening);
+            }
+        } else if !self_closing && !is_void_tag(options.void_tags, name) {
+            open.push((i, name));
+        }
+    }
+
+    let (open_delimiter, close_delimiter) = options.tag_delimiters;
+    let mut result = String::with_capacity(line.len());
+    for (i, segment) in segments.iter().enumerate().rev() {
+        match (*segment, pairs[i]) {
+            (Segment::Text(text), _) => reverse_text(text, &mut result),
+            (Segment::Tag { closing: true, .. }, Some(opening)) => {
+                let Segment::Tag { raw, .. } = segments[opening] else {
+                    unreachable!("tags are only paired with tags");
+                };
+                result.push_str(raw);
+            }
+            (Segment::Tag { name, .. }, Some(_)) => {
+                result.push(open_delimiter);
+                result.push('/');
+                result.push_str(name);
+                result.push(close_delimiter);
+            }
+            (Segment::Tag { raw, .. }, None) => result.push_str(raw),
+        }
+    }
+    result
+}
+
+/// Pushes the graphemes of the text in reverse, keeping the order of left-to-right runs.
+fn reverse_text(text: &str, result: &mut String) {
+    let graphemes: Vec<&str> = text.graphemes(true).rev().collect();
+    let mut i = 0;
+    while i < graphemes.len() {
+        if !is_ltr(graphemes[i]) {
+            result.push_str(mirror(graphemes[i]));
+            i += 1;
+            continue;
+        }
+
+        // The run goes on through neutral characters like spaces and dots, up to the last
+        // left-to-right character before the next right-to-left one
+        let mut end = i;
+        for (j, grapheme) in graphemes.iter().enumerate().skip(i) {
+            if is_rtl(grapheme) {
+                break;
+            }
+            if is_ltr(grapheme) {
+                end = j;
+            }
+        }
+        graphemes[i..=end]
+            .iter()
+            .rev()
+            .for_each(|grapheme| result.push_str(grapheme));
+        i = end + 1;
+    }
+}
+
+fn is_rtl(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(|c| {
+        matches!(c,
+            '\u{590}'..='\u{8ff}'
+            | '\u{fb1d}'..='\u{fdff}'
+            | '\u{fe70}'..='\u{feff}'
+            | '\u{10800}'..='\u{10fff}'
+            | '\u{1e800}'..='\u{1efff}')
+    })
+}
+
+fn is_ltr(grapheme: &str) -> bool {
+    grapheme.chars().next().is_some_and(|c| c.is_alphanumeric()) && !is_rtl(grapheme)
+}
+
+fn mirror(grapheme: &str) -> &str {
+    match grapheme {
+        "(" => ")",
+        ")" => "(",
+        "[" => "]",
+        "]" => "[",
+        "{" => "}",
+        "}" => "{",
+        "«" => "»",
+        "»" => "«",
+        _ => grapheme,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use crate::{wrap_text_with_options, WrapOptions};
+
+    #[test]
+    fn lines_are_in_visual_order() {
+        let options = WrapOptions::new(10).rtl(true);
+        // Numbers keep their order, and every line closes its own tags
+        assert_eq!(
+            wrap_text_with_options("<b>שלום עולם</b> 123 טוב", &options),
+            vec!["<b>םלוע םולש</b>", "בוט 123"]
+        );
+        let options = WrapOptions::new(6).rtl(true);
+        assert_eq!(
+            wrap_text_with_options("مرحبا <b>بالعالم</b>", &options),
+            vec!["ابحرم", "<b>لاعلاب</b>", "<b>م</b>"]
+        );
+    }
+}
@@ -0,0 +1,197 @@
+#[cfg(feature = "wasm")]
+use wasm_bindgen::prelude::*;
+
+use crate::{
+    segment::parse_tag, tag_name, update_open_tags, width::Length, wrap_text_spans_with_options,
+    Align, Segment, WrapOptions,
+};
+
+/// A piece of a wrapped line where the style doesn't change, see [`wrap_text_runs`].
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct Run {
+    /// The text of the run, without any tags.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone))]
+    pub text: String,
+    /// Whether the run is inside of a `<b>` tag.
+    pub bold: bool,
+    /// Whether the run is inside of an `<i>` tag.
+    pub italic: bool,
+    /// Value of the innermost `<color>` tag, e.g. `red` or `#ff0000`.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone))]
+    pub color: Option<String>,
+    /// Font size set by the `<size>` tags, e.g. 20 for `<size=20>`. Percentages and ems are
+    /// relative to an outer `<size>` or the base size given to
+    /// [`wrap_text_runs_with_options`], the size stays `None` outside of `<size>` tags since
+    /// the default size is up to the renderer.
+    pub size: Option<f32>,
+}
+
+/// A wrapped line broken up into [`Run`]s.
+#[cfg_attr(feature = "wasm", wasm_bindgen)]
+#[cfg_attr(feature = "serde", derive(serde::Serialize, serde::Deserialize))]
+#[derive(Debug, Clone, PartialEq)]
+pub struct StyledLine {
+    /// The runs of the line in order, empty for a blank line.
+    #[cfg_attr(feature = "wasm", wasm_bindgen(getter_with_clone))]
+    pub runs: Vec<Run>,
+    /// See [`crate::WrappedLine::align`].
+    pub align: Align,
+    /// See [`crate::WrappedLine::line_height`].
+    #[cfg_attr(feature = "wasm", wasm_bindgen(js_name = lineHeight))]
+    pub line_height: f32,
+}
+
+/// The style of the text inside of the tags that are open.
+#[derive(Debug, Clone, Copy, Default, PartialEq)]
+struct Style<'a> {
+    bold: bool,
+    italic: bool,
+    color: Option<&'a str>,
+    size: Option<f32>,
+}
+
+impl<'a> Style<'a> {
+    /// Resolves the style of the open tags, outermost first. Only `<b>`, `<i>`, `<color>` and
+    /// `<size>` are supported, other tags don't change the style. Relative sizes outside of
+    /// any other `<size>` are relative to `base_size`.
+    fn resolve(open_tags: &[&'a str], base_size: Option<f32>) -> Self {
+        let mut style = Style::default();
+        for &tag in open_tags {
+            let name = tag_name(tag);
+            if name.eq_ignore_ascii_case("b") {
+                style.bold = true;
+            } else if name.eq_ignore_ascii_case("i") {
+                style.italic = true;
+            } else if name.eq_ignore_ascii_case("color") {
+                style.color = parse_tag(tag).value().or(style.color);
+            } else if name.eq_ignore_ascii_case("size") {
+                style.size = parse_tag(tag)
+                    .value()
+                    .and_then(|value| size(value, style.size.or(base_size)))
+                    .or(style.size);
+            }
+        }
+        style
+    }
+}
+
+/// Size given by the value of a `<size>` tag, relative sizes need the size of the outer tag.
+fn size(value: &str, outer: Option<f32>) -> Option<f32> {
+    match Length::parse(value) {
+        Some(Length::Percent(percent)) => outer.map(|size| size * (percent / 100.0) as f32),
+        Some(Length::Em(ems)) => outer.map(|size| size * ems as f32),
+        None => {
+            let value = value.strip_suffix("px").unwrap_or(value);
+            value.trim().parse().ok()
+        }
+    }
+}
+
+/// Like [`crate::wrap_text_spans`], but breaks every line up into runs of text with the styles
+/// of the tags resolved, for renderers that don't parse rich text. The tags themselves are
+/// left out of the runs, including the ones like `<sprite>` that render as something.
+#[cfg_attr(feature = "wasm", wasm_bindgen(js_name = wrapTextRuns))]
+pub fn wrap_text_runs(
+    string: &str,
+    base_line_width: i32,
+    line_width_multiplier: f32,
+) -> Vec<StyledLine> {
+    wrap_text_runs_with_options(
+        string,
+        &WrapOptions::new(base_line_width).multiplier(line_width_multiplier),
+        None,
+    )
+}
+
+/// Like [`wrap_text_runs`], `base_size` is the font size the text is drawn at, so that
+/// `<size>` tags in percentages or ems get a size even if they aren't inside of another one.
+pub fn wrap_text_runs_with_options(
+    string: &str,
+    options: &WrapOptions<'_>,
+    base_size: Option<f32>,
+) -> Vec<StyledLine> {
+    wrap_text_spans_with_options(string, options)
+        .into_iter()
+        .map(|line| {
+            // Tags that are closed on another line would be parsed as text, so the line is
+            // made self-contained like `close_open_tags_per_line` does
+            let (open, close) = options.tag_delimiters;
+            let mut text = line.open_before.concat() + &line.text;
+            for tag in line.open_after.iter().rev() {
+                text.push(open);
+                text.push('/');
+                text.push_str(tag_name(tag));
+                text.push(close);
+            }
+
+            let mut open_tags = Vec::new();
+            let mut runs: Vec<Run> = Vec::new();
+            let mut last_style = None;
+            for segment in options.parsed_tags(&text) {
+                let text = match segment {
+                    Segment::Text(text) => text,
+                    Segment::Tag { raw, .. } => {
+                        update_open_tags(&mut open_tags, raw, options);
+                        continue;
+                    }
+                };
+
+                let style = Style::resolve(&open_tags, base_size);
+                match runs.last_mut() {
+                    Some(run) if last_style == Some(style) => run.text.push_str(text),
+                    _ => runs.push(Run {
+                        text: text.to_string(),
+                        bold: style.bold,
+                        italic: style.italic,
+                        color: style.color.map(str::to_string),
+                        size: style.size,
+                    }),
+                }
+                last_style = Some(style);
+            }
+            StyledLine {
+                runs,
+                align: line.align,
+                line_height: line.line_height,
+            }
+        })
+        .collect()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn sizes(string: &str, base_size: Option<f32>) -> Vec<Option<f32>> {
+        wrap_text_runs_with_options(string, &WrapOptions::new(40), base_size)
+            .into_iter()
+            .flat_map(|line| line.runs)
+            .map(|run| run.size)
+            .collect()
+    }
+
+    #[test]
+    fn relative_sizes() {
+        // Without a base size there's nothing to be relative to, so it's the same run
+        assert_eq!(sizes("a <size=150%>b</size>", None), vec![None]);
+        assert_eq!(
+            sizes("a <size=150%>b</size>", Some(20.0)),
+            vec![None, Some(30.0)]
+        );
+        assert_eq!(
+            sizes("a <size=0.5em>b</size>", Some(20.0)),
+            vec![None, Some(10.0)]
+        );
+        // Nested sizes are relative to the outer one rather than the base size
+        assert_eq!(
+            sizes("<size=10>a <size=2em>b</size></size>", Some(20.0)),
+            vec![Some(10.0), Some(20.0)]
+        );
+        assert_eq!(
+            sizes("<size=50%>a <size=50%>b</size></size>", Some(20.0)),
+            vec![Some(10.0), Some(5.0)]
+        );
+    }
+}
@@ -0,0 +1,173 @@
+use crate::{is_breaking_whitespace, IsolateTags};
+
+/// A section of a rich text string, as yielded by [`ParsedTags`].
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Segment<'a> {
+    Text(&'a str),
+    Tag {
+        /// The full tag, e.g. `<color=#ff0000>`.
+        raw: &'a str,
+        /// The tag name, e.g. `color`.
+        name: &'a str,
+        closing: bool,
+        self_closing: bool,
+    },
+}
+
+impl<'a> Segment<'a> {
+    /// Returns the attributes of a tag as key/value pairs, e.g. `[("name", "coin"), ("index", "3")]`
+    /// for `<sprite name="coin" index=3>`. The anonymous value of a tag like `<color=red>` is
+    /// reported with an empty key. Attributes without a value have an empty value, and malformed
+    /// attributes are skipped.
+    pub fn attributes(&self) -> Vec<(&'a str, &'a str)> {
+        let Segment::Tag { raw, name, .. } = *self else {
+            return Vec::new();
+        };
+
+        // Everything between the tag name and the closing bracket
+        let start = crate::offset_in(raw, name) + name.len();
+        // Tags always end with the closing delimiter, which is ascii
+        let rest = &raw[start..raw.len().max(start + 1) - 1];
+        let rest = rest.strip_suffix('/').unwrap_or(rest);
+
+        // All delimiters are ascii, so it's safe to slice at any of their indices
+        let bytes = rest.as_bytes();
+        let mut attributes = Vec::new();
+        let mut i = 0;
+        loop {
+            while i < bytes.len() && bytes[i].is_ascii_whitespace() {
+                i += 1;
+            }
+            if i >= bytes.len() {
+                break;
+            }
+
+            let key_start = i;
+            while i < bytes.len() && !bytes[i].is_ascii_whitespace() && bytes[i] != b'=' {
+                i += 1;
+            }
+            let key = &rest[key_start..i];
+
+            let mut value = "";
+            if i < bytes.len() && bytes[i] == b'=' {
+                i += 1;
+                if i < bytes.len() && (bytes[i] == b'"' || bytes[i] == b'\'') {
+                    let quote = bytes[i];
+                    let value_start = i + 1;
+                    let Some(len) = bytes[value_start..].iter().position(|&b| b == quote) else {
+                        // Unterminated quote, nothing after this can be trusted
+                        break;
+                    };
+                    value = &rest[value_start..value_start + len];
+                    i = value_start + len + 1;
+                } else {
+                    let value_start = i;
+                    while i < bytes.len() && !bytes[i].is_ascii_whitespace() {
+                        i += 1;
+                    }
+                    value = &rest[value_start..i];
+                }
+            }
+
+            if !key.is_empty() || !value.is_empty() {
+                attributes.push((key, value));
+            }
+        }
+        attributes
+    }
+
+    /// Returns the anonymous value of a tag, e.g. `red` for `<color=red>`.
+    pub fn value(&self) -> Option<&'a str> {
+        self.attributes()
+            .into_iter()
+            .find(|(key, _)| key.is_empty())
+            .map(|(_, value)| value)
+    }
+}
+
+/// Parses a tag chunk (without any trailing whitespace) into a [`Segment::Tag`].
+pub(crate) fn parse_tag(raw: &str) -> Segment<'_> {
+    // The delimiters can be any ascii character, so the first and last bytes are skipped
+    // Spaces before the name are only part of the tag with lenient parsing
+    let inner = raw.get(1..).unwrap_or_default().trim_start_matches(' ');
+    let (inner, closing) = match inner.strip_prefix('/') {
+        Some(inner) => (inner.trim_start_matches(' '), true),
+        None => (inner, false),
+    };
+    // Tag names only contain ascii letters, and hyphens after the first one
+    let name_end = inner
+        .char_indices()
+        .find(|&(i, c)| !(c.is_ascii_alphabetic() || (c == '-' && i > 0)))
+        .map_or(inner.len(), |(i, _)| i);
+    Segment::Tag {
+        raw,
+        name: &inner[..name_end],
+        closing,
+        self_closing: raw.len() > 2 && raw[..raw.len() - 1].ends_with('/'),
+    }
+}
+
+/// Like [`IsolateTags`], but yields structured [`Segment`]s.
+///
+/// Whitespace following a tag is yielded as a separate [`Segment::Text`] so that `raw` only
+/// contains the tag itself.
+pub struct ParsedTags<'a, 't> {
+    inner: IsolateTags<'a, 't>,
+    pending_text: Option<&'a str>,
+}
+
+impl<'a, 't> ParsedTags<'a, 't> {
+    pub fn new(s: &'a str) -> Self {
+        Self {
+            inner: IsolateTags::new(s),
+            pending_text: None,
+        }
+    }
+
+    /// See [`IsolateTags::with_delimiters`].
+    pub fn with_delimiters(s: &'a str, open: char, close: char) -> Self {
+        Self {
+            inner: IsolateTags::with_delimiters(s, open, close),
+            pending_text: None,
+        }
+    }
+
+    /// See [`IsolateTags::lenient`].
+    pub fn lenient(mut self, lenient: bool) -> Self {
+        self.inner = self.inner.lenient(lenient);
+        self
+    }
+
+    /// See [`IsolateTags::void_tags`].
+    pub fn void_tags(mut self, void_tags: &'t [&'t str]) -> Self {
+        self.inner = self.inner.void_tags(void_tags);
+        self
+    }
+
+    /// See [`IsolateTags::unclosed_tags`].
+    pub fn unclosed_tags(mut self, unclosed_tags: &'t [&'t str]) -> Self {
+        self.inner = self.inner.unclosed_tags(unclosed_tags);
+        self
+    }
+}
+
+impl<'a> Iterator for ParsedTags<'a, '_> {
+    type Item = Segment<'a>;
+
+    fn next(&mut self) -> Option<Self::Item> {
+        if let Some(text) = self.pending_text.take() {
+            return Some(Segment::Text(text));
+        }
+
+        let (chunk, is_tag) = self.inner.next()?;
+        if !is_tag {
+            return Some(Segment::Text(chunk));
+        }
+
+        let tag_end = chunk.trim_end_matches(is_breaking_whitespace).len();
+        if tag_end < chunk.len() {
+            self.pending_text = Some(&chunk[tag_end..]);
+        }
+        Some(parse_tag(&chunk[..tag_end]))
+    }
+}
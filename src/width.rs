@@ -0,0 +1,272 @@
+use std::collections::HashMap;
+
+use unicode_width::UnicodeWidthChar;
+
+use crate::Segment;
+
+/// Strategy used to measure the display width of non-tag characters.
+#[derive(Clone, Copy, Default)]
+pub enum CharWidth<'a> {
+    /// East Asian width, full-width characters like `あ` or `文` take up 2 columns.
+    #[default]
+    Unicode,
+    /// Every character takes up 1 column.
+    Monospace,
+    /// User supplied width function, e.g. for using the glyph advances of a font.
+    Custom(&'a dyn Fn(char) -> f64),
+    /// The advances of a font, see [`crate::WrapOptions::with_font_metrics`].
+    Font(&'a FontMetrics),
+}
+
+impl std::fmt::Debug for CharWidth<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            CharWidth::Unicode => f.write_str("Unicode"),
+            CharWidth::Monospace => f.write_str("Monospace"),
+            CharWidth::Custom(width) => write!(f, "Custom({width:p})"),
+            CharWidth::Font(metrics) => f.debug_tuple("Font").field(metrics).finish(),
+        }
+    }
+}
+
+impl CharWidth<'_> {
+    pub fn char_width(&self, c: char) -> f64 {
+        match self {
+            CharWidth::Unicode => c.width().unwrap_or(0) as f64,
+            CharWidth::Monospace => 1.0,
+            CharWidth::Custom(f) => f(c),
+            CharWidth::Font(metrics) => f64::from(metrics.advance(c)),
+        }
+    }
+
+    pub fn str_width(&self, s: &str) -> f64 {
+        s.chars().map(|c| self.char_width(c)).sum()
+    }
+
+    /// Like [`CharWidth::str_width`], but the chars are measured in the case they're rendered
+    /// in, e.g. `a` is as wide as `A` inside of `<uppercase>`.
+    pub(crate) fn str_width_in_case(&self, s: &str, case: Option<Case>) -> f64 {
+        let Some(case) = case else {
+            return self.str_width(s);
+        };
+        let width_of = |chars: &mut dyn Iterator<Item = char>| -> f64 {
+            chars.map(|c| self.char_width(c)).sum()
+        };
+        s.chars()
+            .map(|c| match case {
+                Case::Upper => width_of(&mut c.to_uppercase()),
+                Case::Lower => width_of(&mut c.to_lowercase()),
+                Case::SmallCaps if c.is_lowercase() => {
+                    width_of(&mut c.to_uppercase()) * SMALL_CAPS_SCALE
+                }
+                Case::SmallCaps => self.char_width(c),
+            })
+            .sum()
+    }
+
+    /// Like [`CharWidth::str_width_in_case`], but `&lt;`, `&gt;` and `&amp;` are measured as
+    /// the character they stand for.
+    pub(crate) fn str_width_decoding_entities(&self, s: &str, case: Option<Case>) -> f64 {
+        const ENTITIES: [(&str, char); 3] = [("&lt;", '<'), ("&gt;", '>'), ("&amp;", '&')];
+
+        let mut width = 0.0;
+        let mut rest = s;
+        while let Some(i) = rest.find('&') {
+            width += self.str_width_in_case(&rest[..i], case);
+            rest = &rest[i..];
+            let (len, c) = ENTITIES
+                .iter()
+                .find(|(entity, _)| rest.starts_with(entity))
+                .map_or((1, '&'), |&(entity, c)| (entity.len(), c));
+            width += self.char_width(c);
+            rest = &rest[len..];
+        }
+        width + self.str_width_in_case(rest, case)
+    }
+}
+
+/// The horizontal advances of the glyphs of a font, in pixels.
+#[derive(Debug, Clone, Default, PartialEq)]
+pub struct FontMetrics {
+    pub advances: HashMap<char, f32>,
+    /// Advance of the characters that aren't in [`Self::advances`].
+    pub default_advance: f32,
+}
+
+impl FontMetrics {
+    pub fn new(advances: HashMap<char, f32>, default_advance: f32) -> Self {
+        Self {
+            advances,
+            default_advance,
+        }
+    }
+
+    /// Advance of the character, characters that aren't in the table use the default advance
+    /// unless they're zero-width like combining marks.
+    pub fn advance(&self, c: char) -> f32 {
+        match self.advances.get(&c) {
+            Some(&advance) => advance,
+            None if c.width() == Some(0) => 0.0,
+            None => self.default_advance,
+        }
+    }
+}
+
+/// Width function called with a tag name and its attributes.
+pub type TagWidthFn<'a> = dyn Fn(&str, &[(&str, &str)]) -> f64 + 'a;
+
+/// Width of tags that render as glyphs, like `<sprite>` and `<quad>`. Closing tags always have
+/// zero width.
+#[derive(Clone, Copy, Default)]
+pub enum TagWidth<'a> {
+    /// Every tag is zero-width formatting.
+    #[default]
+    Zero,
+    /// Fixed widths by tag name (e.g. `[("sprite", 1.0)]`), other tags have zero width.
+    Names(&'a [(&'a str, f64)]),
+    /// User supplied width function called with the tag name and its attributes.
+    Custom(&'a TagWidthFn<'a>),
+}
+
+impl std::fmt::Debug for TagWidth<'_> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self {
+            TagWidth::Zero => f.write_str("Zero"),
+            TagWidth::Names(names) => f.debug_tuple("Names").field(names).finish(),
+            TagWidth::Custom(width) => write!(f, "Custom({width:p})"),
+        }
+    }
+}
+
+impl TagWidth<'_> {
+    pub fn tag_width(&self, tag: &Segment<'_>) -> f64 {
+        let Segment::Tag {
+            name,
+            closing: false,
+            ..
+        } = *tag
+        else {
+            return 0.0;
+        };
+
+        match self {
+            TagWidth::Zero => 0.0,
+            TagWidth::Names(names) => names
+                .iter()
+                .find(|(n, _)| n.eq_ignore_ascii_case(name))
+                .map_or(0.0, |(_, width)| *width),
+            TagWidth::Custom(f) => f(name, &tag.attributes()),
+        }
+    }
+}
+
+/// A length given as a tag value, like `150%` or `2.5em`.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub(crate) enum Length {
+    Percent(f64),
+    Em(f64),
+}
+
+impl Length {
+    /// Parses the value, lengths in other units like pixels return `None`.
+    pub fn parse(value: &str) -> Option<Self> {
+        if let Some(percent) = value.strip_suffix('%') {
+            percent.trim().parse().ok().map(Length::Percent)
+        } else if let Some(ems) = value.strip_suffix("em") {
+            ems.trim().parse().ok().map(Length::Em)
+        } else {
+            None
+        }
+    }
+
+    /// Width in columns, percentages are relative to `width`.
+    pub fn columns(self, width: f64, em_width: f64) -> f64 {
+        match self {
+            Length::Percent(percent) => width * percent / 100.0,
+            Length::Em(ems) => ems * em_width,
+        }
+    }
+}
+
+/// The case that the text inside of `<uppercase>`, `<lowercase>` or `<smallcaps>` is rendered
+/// in. It only changes how the text is measured, the text itself keeps its case.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub(crate) enum Case {
+    Upper,
+    Lower,
+    /// Lowercase letters are rendered as smaller capitals, see [`SMALL_CAPS_SCALE`].
+    SmallCaps,
+}
+
+/// Size of the capitals that lowercase letters are rendered as inside of `<smallcaps>`.
+const SMALL_CAPS_SCALE: f64 = 0.8;
+
+/// The scale of the text inside of the `<size>` tags that are currently open, along with the
+/// `<mspace>` and the case tag that are active.
+///
+/// Only percentages like `<size=150%>` are supported, other sizes keep the current scale.
+#[derive(Debug, Clone, Default)]
+pub(crate) struct SizeStack {
+    scales: Vec<f64>,
+    /// Advance of every char in ems, e.g. 2 inside of `<mspace=2em>`.
+    mspace: Option<f64>,
+    case: Option<Case>,
+}
+
+impl SizeStack {
+    pub fn update(&mut self, tag: &Segment<'_>) {
+        let Segment::Tag { name, closing, .. } = *tag else {
+            return;
+        };
+        if name.eq_ignore_ascii_case("mspace") {
+            // `</mspace>` ends the monospacing, it doesn't go back to an outer one
+            self.mspace = match tag.value().and_then(Length::parse) {
+                Some(Length::Em(ems)) if !closing => Some(ems.max(0.0)),
+                _ => None,
+            };
+            return;
+        }
+        let case = if name.eq_ignore_ascii_case("uppercase") || name.eq_ignore_ascii_case("allcaps")
+        {
+            Some(Case::Upper)
+        } else if name.eq_ignore_ascii_case("lowercase") {
+            Some(Case::Lower)
+        } else if name.eq_ignore_ascii_case("smallcaps") {
+            Some(Case::SmallCaps)
+        } else {
+            None
+        };
+        if let Some(case) = case {
+            // Like `</mspace>`, closing any of them goes back to the original case
+            self.case = (!closing).then_some(case);
+            return;
+        }
+        if !name.eq_ignore_ascii_case("size") {
+            return;
+        }
+
+        if closing {
+            self.scales.pop();
+        } else {
+            let scale = match tag.value().and_then(Length::parse) {
+                Some(Length::Percent(percent)) => percent / 100.0,
+                _ => self.scale(),
+            };
+            self.scales.push(scale);
+        }
+    }
+
+    pub fn scale(&self) -> f64 {
+        self.scales.last().copied().unwrap_or(1.0)
+    }
+
+    /// Advance of every char in ems while monospacing, see [`SizeStack::update`].
+    pub fn mspace(&self) -> Option<f64> {
+        self.mspace
+    }
+
+    /// The case the text is rendered in, see [`Case`].
+    pub fn case(&self) -> Option<Case> {
+        self.case
+    }
+}
@@ -0,0 +1,364 @@
+use textwrap::{core::Fragment, WordSplitter};
+use unicode_segmentation::UnicodeSegmentation;
+
+use crate::{width::Case, CharWidth, KinsokuChars};
+
+const SOFT_HYPHEN: char = '\u{ad}';
+
+/// A piece of wrappable text, like [`textwrap::core::Word`] but measured with a [`CharWidth`]
+/// instead of always using the unicode display width.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Word<'a> {
+    /// The text of the word, or the whole tag.
+    pub word: &'a str,
+    /// The whitespace after the word.
+    pub whitespace: &'a str,
+    /// Shown at the end of the line if it's broken after the word, e.g. a hyphen.
+    pub penalty: &'a str,
+    /// Whether this word is a formatting tag rather than text.
+    pub is_tag: bool,
+    /// Whether a line break before this word is forbidden.
+    pub glue: bool,
+    /// Whether the word was cut off and is followed by the ellipsis, see
+    /// [`crate::Overflow::Truncate`].
+    pub truncated: bool,
+    width: f64,
+    whitespace_width: f64,
+    penalty_width: f64,
+    /// Factor the char widths are scaled by, e.g. inside of a `<size>` tag.
+    scale: f64,
+    /// Width of every grapheme cluster inside of `<mspace>`, instead of measuring them.
+    advance: Option<f64>,
+    /// Case the word is measured in, e.g. inside of `<uppercase>`.
+    case: Option<Case>,
+    /// Whether the word is a part of a compound like `well-known` that was only split at its
+    /// hyphens, see [`Word::hyphenate`].
+    compound: bool,
+    /// Whether the word is inside of `<nobr>`, so it's never hyphenated or broken apart.
+    pub(crate) nobr: bool,
+}
+
+impl std::ops::Deref for Word<'_> {
+    type Target = str;
+
+    fn deref(&self) -> &Self::Target {
+        self.word
+    }
+}
+
+impl<'a> Word<'a> {
+    pub(crate) fn new(
+        word: &'a str,
+        whitespace: &'a str,
+        penalty: &'a str,
+        char_width: &CharWidth<'_>,
+    ) -> Self {
+        Self {
+            word,
+            whitespace,
+            penalty,
+            is_tag: false,
+            glue: false,
+            truncated: false,
+            width: char_width.str_width(word),
+            whitespace_width: char_width.str_width(whitespace),
+            penalty_width: char_width.str_width(penalty),
+            scale: 1.0,
+            advance: None,
+            case: None,
+            compound: false,
+            nobr: false,
+        }
+    }
+
+    /// Splits a word at the points given by the splitter, like
+    /// [`textwrap::word_splitters::split_words`] but without tying the lifetime of the words to
+    /// the splitter.
+    ///
+    /// Soft hyphens (U+00AD) are break opportunities too, they are removed from the words and
+    /// only show up as a `-` penalty when the line is broken there. Compounds that already
+    /// have hyphens are only split at those, the dictionary of a hyphenating splitter is left
+    /// for the parts that don't fit on a line.
+    pub(crate) fn split(
+        word: textwrap::core::Word<'a>,
+        splitter: &WordSplitter,
+        char_width: &CharWidth<'_>,
+    ) -> Vec<Self> {
+        let mut words: Vec<Self> = Vec::new();
+        let mut parts = word.word.split(SOFT_HYPHEN).peekable();
+        while let Some(part) = parts.next() {
+            let first_word = words.len();
+            let existing_hyphens = match splitter {
+                WordSplitter::HyphenSplitter => Vec::new(),
+                _ if !part.contains('-') => Vec::new(),
+                _ => WordSplitter::HyphenSplitter.split_points(part),
+            };
+            let compound = !existing_hyphens.is_empty();
+            let split_points = if compound {
+                existing_hyphens
+            } else {
+                splitter.split_points(part)
+            };
+
+            let mut prev = 0;
+            for idx in split_points {
+                let penalty = if part[..idx].ends_with('-') { "" } else { "-" };
+                words.push(Self::new(&part[prev..idx], "", penalty, char_width));
+                prev = idx;
+            }
+
+            let rest = &part[prev..];
+            if parts.peek().is_some() {
+                if !rest.is_empty() {
+                    words.push(Self::new(rest, "", "-", char_width));
+                }
+            } else if let Some(last) = words.last_mut().filter(|_| rest.is_empty()) {
+                // A trailing soft hyphen, the word ends where the whitespace starts
+                *last = Self::new(last.word, word.whitespace, word.penalty, char_width);
+            } else {
+                words.push(Self::new(rest, word.whitespace, word.penalty, char_width));
+            }
+            for word in &mut words[first_word..] {
+                word.compound = compound;
+            }
+        }
+        words
+    }
+
+    /// Splits a part of a compound at the hyphenation points of the splitter, for when it's
+    /// too long to fit on a line. Other words are returned as is since they were already
+    /// hyphenated by [`Word::split`].
+    pub(crate) fn hyphenate(
+        &self,
+        splitter: &WordSplitter,
+        char_width: &CharWidth<'_>,
+    ) -> Vec<Word<'a>> {
+        // The hyphen at the end of a part like `well-` is already a break point
+        let text = self.word.strip_suffix('-').unwrap_or(self.word);
+        let split_points = splitter.split_points(text);
+        if !self.compound || split_points.is_empty() {
+            return vec![*self];
+        }
+
+        let mut words = Vec::with_capacity(split_points.len() + 1);
+        let mut prev = 0;
+        for idx in split_points.into_iter().chain([self.word.len()]) {
+            let last = idx == self.word.len();
+            let mut word = if last {
+                Self::new(
+                    &self.word[prev..],
+                    self.whitespace,
+                    self.penalty,
+                    char_width,
+                )
+            } else {
+                Self::new(&self.word[prev..idx], "", "-", char_width)
+            };
+            word.glue = self.glue && prev == 0;
+            if let Some(case) = self.case {
+                word.set_case(case, char_width);
+            }
+            if let Some(advance) = self.advance {
+                word.set_advance(advance);
+            }
+            word.scale_by(self.scale);
+            words.push(word);
+            prev = idx;
+        }
+        words
+    }
+
+    pub(crate) fn set_width(&mut self, width: f64) {
+        self.width = width;
+    }
+
+    /// Gives every grapheme cluster of the word, its whitespace and its penalty the same width,
+    /// e.g. inside of `<mspace>`.
+    pub(crate) fn set_advance(&mut self, advance: f64) {
+        let width = |s: &str| s.graphemes(true).count() as f64 * advance * self.scale;
+        self.width = width(self.word);
+        self.whitespace_width = width(self.whitespace);
+        self.penalty_width = width(self.penalty);
+        self.advance = Some(advance);
+    }
+
+    /// Measures the word in the case it's rendered in, e.g. inside of `<uppercase>`. The
+    /// whitespace and penalty aren't letters, so they keep their widths.
+    pub(crate) fn set_case(&mut self, case: Case, char_width: &CharWidth<'_>) {
+        self.width = char_width.str_width_in_case(self.word, Some(case)) * self.scale;
+        self.case = Some(case);
+    }
+
+    /// Scales the widths of the word, e.g. for text that is drawn at a larger size.
+    pub(crate) fn scale_by(&mut self, scale: f64) {
+        self.width *= scale;
+        self.whitespace_width *= scale;
+        self.penalty_width *= scale;
+        self.scale *= scale;
+    }
+
+    /// Adds whitespace that is drawn after the word, e.g. the whitespace after the formatting
+    /// tags that follow it.
+    pub(crate) fn add_whitespace_width(&mut self, width: f64) {
+        self.whitespace_width += width;
+    }
+
+    /// Count the whitespace as part of the word, so it isn't free at the end of a line.
+    pub(crate) fn absorb_whitespace(&mut self) {
+        self.width += self.whitespace_width;
+        self.whitespace_width = 0.0;
+    }
+
+    /// Break this word into smaller words with a width of at most `line_width`. The whitespace
+    /// and penalty are added to the last piece.
+    ///
+    /// The word is only broken between grapheme clusters, so combining marks and emoji
+    /// sequences like 👨‍👩‍👧 stay intact. A cluster that is wider than the line gets a piece of
+    /// its own. With `kinsoku`, pieces don't end with opening brackets or start with closing
+    /// brackets and punctuation, even if they get a little wider than the line for it.
+    pub(crate) fn break_apart<'b>(
+        &'b self,
+        line_width: f64,
+        char_width: &'b CharWidth<'_>,
+        kinsoku: Option<&'b KinsokuChars<'_>>,
+    ) -> impl Iterator<Item = Word<'a>> + 'b {
+        let graphemes: Vec<_> = self.word.grapheme_indices(true).collect();
+        let grapheme_width = move |grapheme: &str| {
+            self.advance
+                .unwrap_or_else(|| char_width.str_width_in_case(grapheme, self.case))
+                * self.scale
+        };
+        let mut start = 0;
+
+        std::iter::from_fn(move || {
+            if start == graphemes.len() {
+                return None;
+            }
+
+            let mut end = start;
+            let mut width = 0.0;
+            while end < graphemes.len() {
+                let c_width = grapheme_width(graphemes[end].1);
+                if end > start && width + c_width > line_width {
+                    break;
+                }
+                width += c_width;
+                end += 1;
+            }
+            if let Some(chars) = kinsoku.filter(|_| end < graphemes.len()) {
+                // Move the break in front of the opening brackets at the end of the piece, or
+                // past them if there's nothing else, then pull the closing ones after it in
+                let mut before = end;
+                while before > start && chars.is_no_end(graphemes[before - 1].1) {
+                    before -= 1;
+                }
+                if before == start {
+                    end += 1;
+                } else {
+                    end = before;
+                }
+                while end < graphemes.len() && chars.is_no_start(graphemes[end].1) {
+                    end += 1;
+                }
+                width = graphemes[start..end]
+                    .iter()
+                    .map(|(_, grapheme)| grapheme_width(grapheme))
+                    .sum();
+            }
+
+            let offset = graphemes[start].0;
+            let first = start == 0;
+            start = end;
+            if end < graphemes.len() {
+                Some(Word {
+                    word: &self.word[offset..graphemes[end].0],
+                    whitespace: "",
+                    penalty: "",
+                    is_tag: self.is_tag,
+                    // Only the first piece keeps the glue, the point is to break it
+                    glue: self.glue && first,
+                    truncated: false,
+                    width,
+                    whitespace_width: 0.0,
+                    penalty_width: 0.0,
+                    scale: self.scale,
+                    advance: self.advance,
+                    case: self.case,
+                    compound: self.compound,
+                    nobr: self.nobr,
+                })
+            } else {
+                Some(Word {
+                    word: &self.word[offset..],
+                    glue: self.glue && first,
+                    width,
+                    ..*self
+                })
+            }
+        })
+    }
+
+    /// Cut this word off so that it fits in `line_width` along with the ellipsis after it. The
+    /// first grapheme cluster is always kept, even if it doesn't fit.
+    pub(crate) fn truncate(
+        &self,
+        line_width: f64,
+        ellipsis: &str,
+        char_width: &CharWidth<'_>,
+    ) -> Word<'a> {
+        let ellipsis_width = char_width.str_width(ellipsis) * self.scale;
+        let first = self
+            .break_apart((line_width - ellipsis_width).max(0.0), char_width, None)
+            .next()
+            .unwrap_or(*self);
+        Word {
+            word: first.word,
+            penalty: "",
+            truncated: true,
+            width: first.width + ellipsis_width,
+            penalty_width: 0.0,
+            ..*self
+        }
+    }
+}
+
+impl Fragment for Word<'_> {
+    #[inline]
+    fn width(&self) -> f64 {
+        self.width
+    }
+
+    #[inline]
+    fn whitespace_width(&self) -> f64 {
+        self.whitespace_width
+    }
+
+    #[inline]
+    fn penalty_width(&self) -> f64 {
+        self.penalty_width
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn break_apart_keeps_grapheme_clusters() {
+        let family = "\u{1F468}\u{200D}\u{1F469}\u{200D}\u{1F467}";
+        let word = family.repeat(3);
+        let word = Word::new(&word, "", "", &CharWidth::Unicode);
+        let pieces: Vec<_> = word
+            .break_apart(3.0, &CharWidth::Unicode, None)
+            .map(|piece| piece.word)
+            .collect();
+        assert_eq!(pieces, vec![family; 3]);
+
+        let word = Word::new("e\u{301}e\u{301}e\u{301}", "", "", &CharWidth::Unicode);
+        let pieces: Vec<_> = word
+            .break_apart(2.0, &CharWidth::Unicode, None)
+            .map(|piece| piece.word)
+            .collect();
+        assert_eq!(pieces, vec!["e\u{301}e\u{301}", "e\u{301}"]);
+    }
+}
@@ -0,0 +1,128 @@
+use hachimi_lib::{
+    isolate_tags_with_whitespace_owned, wrap_text, wrap_text_spans, wrap_text_with_options,
+    IsolateTags, TagScanner, TagWhitespace, WrapOptions,
+};
+use proptest::prelude::*;
+
+/// Tag soup made of the pieces that matter to the scanner, including multi-byte chars and
+/// whitespace next to the brackets.
+fn tag_soup(quotes: bool) -> impl Strategy<Value = String> {
+    let mut pieces = vec![
+        "<", ">", "</", "/>", "=", " ", "\u{3000}", "\n", "-", "b", "i", "color", "size", "br",
+        "noparse", "あ", "é", "x",
+    ];
+    if quotes {
+        pieces.extend(["\"", "'"]);
+    }
+    prop::collection::vec(prop::sample::select(pieces), 0..24).prop_map(|pieces| pieces.concat())
+}
+
+fn chunk_offsets(s: &str, chunks: &[(&str, bool)]) -> Vec<usize> {
+    chunks
+        .iter()
+        .map(|(chunk, _)| chunk.as_ptr() as usize - s.as_ptr() as usize)
+        .collect()
+}
+
+proptest! {
+    #[test]
+    fn chunks_join_back_into_the_input(s in tag_soup(true)) {
+        let chunks: Vec<_> = IsolateTags::new(&s).collect();
+        prop_assert!(chunks.iter().all(|(chunk, _)| !chunk.is_empty()));
+        let joined: String = chunks.iter().map(|(chunk, _)| *chunk).collect();
+        prop_assert_eq!(joined, s);
+    }
+
+    #[test]
+    fn owned_chunks_join_back_into_the_input(s in tag_soup(true)) {
+        for tag_whitespace in [TagWhitespace::WithTag, TagWhitespace::WithText] {
+            let joined: String = isolate_tags_with_whitespace_owned(&s, tag_whitespace)
+                .into_iter()
+                .map(|section| section.chunk)
+                .collect();
+            prop_assert_eq!(&joined, &s);
+        }
+    }
+
+    // Breaking only takes away whitespace, the text and tags of the lines are the input's,
+    // apart from `<br>` which is turned into a line break
+    #[test]
+    fn wrapped_lines_keep_everything_but_whitespace(
+        s in tag_soup(true).prop_map(|s| s.replace("br", "p")),
+        width in 1..12,
+    ) {
+        let without_whitespace = |s: &str| s.split_whitespace().collect::<String>();
+        let joined: String = wrap_text_spans(&s, width, 1.0)
+            .into_iter()
+            .map(|line| line.text)
+            .collect();
+        prop_assert_eq!(without_whitespace(&joined), without_whitespace(&s));
+    }
+
+    #[test]
+    fn arbitrary_strings_join_back_into_the_input(s in any::<String>()) {
+        let joined: String = IsolateTags::new(&s).map(|(chunk, _)| chunk).collect();
+        prop_assert_eq!(joined, s);
+    }
+
+    #[test]
+    fn chunks_start_at_char_boundaries(s in tag_soup(true)) {
+        let chunks: Vec<_> = IsolateTags::new(&s).collect();
+        for offset in chunk_offsets(&s, &chunks) {
+            prop_assert!(s.is_char_boundary(offset));
+        }
+    }
+
+    // Quoted values that contain whole tags are the one case where the back can differ
+    #[test]
+    fn reverse_yields_the_same_chunks(s in tag_soup(false)) {
+        let forward: Vec<_> = IsolateTags::new(&s).collect();
+        let mut backward: Vec<_> = IsolateTags::new(&s).rev().collect();
+        backward.reverse();
+        prop_assert_eq!(forward, backward);
+    }
+
+    #[test]
+    fn scanner_yields_the_same_chunks(s in tag_soup(true)) {
+        let scanned: Vec<_> = TagScanner::new(s.clone())
+            .map(|section| (section.chunk, section.is_tag))
+            .collect();
+        let chunks: Vec<_> = IsolateTags::new(&s)
+            .map(|(chunk, is_tag)| (chunk.to_owned(), is_tag))
+            .collect();
+        prop_assert_eq!(scanned, chunks);
+    }
+
+    // Long runs of text between the tags are skipped with memchr
+    #[test]
+    fn long_text_between_tags(
+        parts in prop::collection::vec(
+            (
+                "[a-zあ.,][a-z あ.,]{0,200}",
+                prop::sample::select(vec!["<br>", "<sprite=1>", "<page>"]),
+            ),
+            1..8,
+        ),
+    ) {
+        let expected: Vec<_> = parts
+            .iter()
+            .flat_map(|(text, tag)| [(text.as_str(), false), (*tag, true)])
+            .collect();
+        let s: String = expected.iter().map(|(chunk, _)| *chunk).collect();
+        let chunks: Vec<_> = IsolateTags::new(&s).collect();
+        prop_assert_eq!(chunks, expected);
+
+        let s = s.replace('<', "[").replace('>', "]");
+        let chunks: Vec<_> = IsolateTags::with_delimiters(&s, '[', ']').collect();
+        let joined: String = chunks.iter().map(|(chunk, _)| *chunk).collect();
+        prop_assert_eq!(&joined, &s);
+        prop_assert_eq!(chunks.iter().filter(|(_, is_tag)| *is_tag).count(), parts.len());
+    }
+
+    // Lines without tags that fit as a whole skip splitting the words, which mustn't change them
+    #[test]
+    fn tag_free_lines_wrap_like_any_other(s in "[a-z あ\n-]{0,40}", width in 1..30) {
+        let options = WrapOptions::new(width).max_words_per_line(usize::MAX);
+        prop_assert_eq!(wrap_text(&s, width, 1.0), wrap_text_with_options(&s, &options));
+    }
+}